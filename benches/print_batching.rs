@@ -0,0 +1,47 @@
+// Compares per-char Print events against coalesced PrintStr runs on a large
+// ASCII payload. Run with `cargo bench --bench print_batching`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lettuce::parser::Parser;
+use lettuce::terminal::Terminal;
+
+const ITERATIONS: u32 = 5;
+
+fn payload() -> Vec<u8> {
+    (0..2_000_000)
+        .map(|i| match i % 80 {
+            78 => b'\r',
+            79 => b'\n',
+            n => b'a' + (n % 26) as u8,
+        })
+        .collect()
+}
+
+fn run(batch: bool, data: &[u8]) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut parser = Parser::new();
+    parser.set_batch_prints(batch);
+    parser.advance(&mut vte::Parser::new(), data);
+    let mut terminal = Terminal::new(80, 24);
+    terminal.process_events(&parser.events);
+    black_box(&terminal);
+    (start.elapsed(), parser.events.len())
+}
+
+fn main() {
+    let data = payload();
+    for batch in [false, true] {
+        let mut best = Duration::MAX;
+        let mut events = 0;
+        for _ in 0..ITERATIONS {
+            let (elapsed, count) = run(batch, &data);
+            best = best.min(elapsed);
+            events = count;
+        }
+        println!(
+            "batch_prints={batch:<5} events={events:>8} best of {ITERATIONS}: {best:?} ({:.1} MB/s)",
+            data.len() as f64 / best.as_secs_f64() / 1_000_000.0
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd};
+
+use anyhow::{Context, Result};
+use nix::fcntl::{FcntlArg, OFlag, fcntl};
+use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, native_pty_system};
+use tokio::io::unix::AsyncFd;
+
+use crate::terminal::Terminal;
+
+// A child process on a PTY wired to a Terminal, read through the tokio
+// reactor instead of a background thread. The master is switched to
+// non-blocking mode and registered with `AsyncFd`, so `read_and_apply` only
+// wakes when the child has written something. Must be created inside a tokio
+// runtime.
+pub struct AsyncSession {
+    terminal: Terminal,
+    // Owns the PTY; `pty` is a duplicate of its descriptor
+    master: Box<dyn MasterPty + Send>,
+    pty: AsyncFd<File>,
+    child: Box<dyn Child + Send + Sync>,
+}
+
+impl AsyncSession {
+    pub fn spawn(command: CommandBuilder, cols: usize, rows: usize) -> Result<Self> {
+        let terminal = Terminal::new(cols, rows);
+        let (cols, rows) = terminal.size();
+        let pair = native_pty_system().openpty(pty_size(cols, rows))?;
+        let child = pair.slave.spawn_command(command)?;
+
+        let fd = pair
+            .master
+            .as_raw_fd()
+            .context("PTY master has no file descriptor")?;
+        // SAFETY: the descriptor belongs to `pair.master`, which outlives this borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+        let flags = OFlag::from_bits_truncate(fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL)?);
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        let pty = AsyncFd::new(File::from(fd))?;
+
+        Ok(AsyncSession {
+            terminal,
+            master: pair.master,
+            pty,
+            child,
+        })
+    }
+
+    /// Waits for output from the child and feeds it to the terminal.
+    /// Returns the terminal's replies to write back to the child (possibly
+    /// empty), or None once the child has hung up.
+    pub async fn read_and_apply(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let mut guard = self.pty.readable().await?;
+            match guard.try_io(|pty| pty.get_ref().read(&mut buffer)) {
+                Ok(Ok(0)) => return Ok(None),
+                Ok(Ok(n)) => {
+                    self.terminal.feed(&buffer[..n]);
+                    return Ok(Some(self.terminal.take_responses()));
+                }
+                // Linux reports a closed slave side as EIO rather than EOF
+                Ok(Err(err)) if err.raw_os_error() == Some(nix::libc::EIO) => return Ok(None),
+                Ok(Err(err)) => return Err(err),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            let mut guard = self.pty.writable().await?;
+            match guard.try_io(|pty| pty.get_ref().write(bytes)) {
+                Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(Ok(n)) => bytes = &bytes[n..],
+                Ok(Err(err)) => return Err(err),
+                Err(_would_block) => continue,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resizes the terminal and tells the child's PTY the new size.
+    pub fn resize(&mut self, cols: usize, rows: usize) -> Result<()> {
+        self.terminal.resize(cols, rows);
+        let (cols, rows) = self.terminal.size();
+        self.master.resize(pty_size(cols, rows))?;
+        Ok(())
+    }
+
+    pub fn terminal(&self) -> &Terminal {
+        &self.terminal
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut Terminal {
+        &mut self.terminal
+    }
+
+    pub fn wait(&mut self) -> io::Result<portable_pty::ExitStatus> {
+        self.child.wait()
+    }
+}
+
+fn pty_size(cols: usize, rows: usize) -> PtySize {
+    PtySize {
+        rows: rows.min(u16::MAX as usize) as u16,
+        cols: cols.min(u16::MAX as usize) as u16,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn read_and_apply_feeds_child_output() {
+        if !Path::new("/bin/echo").exists() {
+            return;
+        }
+        let mut command = CommandBuilder::new("/bin/echo");
+        command.arg("hi");
+        let mut session = AsyncSession::spawn(command, 20, 3).unwrap();
+        while !session.terminal().render_content().contains("hi") {
+            if session.read_and_apply().await.unwrap().is_none() {
+                break;
+            }
+        }
+        assert!(session.terminal().render_content().starts_with("hi"));
+        session.wait().unwrap();
+    }
+}
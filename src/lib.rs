@@ -0,0 +1,47 @@
+// Parsing and grid state only need allocation; PTY and I/O support live behind `std`
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "tokio", unix))]
+pub mod async_session;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod recorder;
+#[cfg(feature = "std")]
+pub mod session;
+pub mod terminal;
+
+// Only built by `cargo test --no-default-features --lib`, with the crate in
+// no_std mode. That the core links without std at all is checked by building
+// for a bare-metal target:
+// `cargo build --lib --no-default-features --target thumbv7em-none-eabihf`
+#[cfg(all(test, not(feature = "std")))]
+mod no_std {
+    use alloc::vec::Vec;
+
+    use crate::parser::{Color, Parser, TerminalEvent};
+    use crate::terminal::Terminal;
+
+    #[test]
+    fn terminal_feeds_bytes_without_std() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.feed(b"\x1b[31mhi\x1b[0m\r\nthere\x1b[6n");
+        assert_eq!(terminal.cell(0, 0).unwrap().character(), 'h');
+        assert_eq!(terminal.attrs_at(0, 1).unwrap().foreground, Color::Red);
+        assert!(terminal.render_content().starts_with("hi"));
+        assert_eq!(terminal.take_responses(), b"\x1b[2;6R");
+
+        let mut parser = Parser::new();
+        parser.advance(&mut vte::Parser::new(), "é中".as_bytes());
+        let printed: Vec<char> = parser
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                TerminalEvent::Print { char, .. } => Some(*char),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(printed, ['é', '中']);
+    }
+}
@@ -1,529 +1,9 @@
 use anyhow::{Ok, Result};
+use lettuce::terminal::Terminal;
 use portable_pty::{CommandBuilder, PtySize, native_pty_system};
 use std::io::{Read, Write};
 use std::thread;
 use std::time::Duration;
-use vte::{Params, Perform};
-
-// Session 2 Part 1 - Color, Attributes, Events
-
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
-enum Color {
-    #[default]
-    Default,
-    Black,
-    Red,
-    Green,
-    Yellow,
-    Blue,
-    Magenta,
-    Cyan,
-    White,
-    BrightBlack,
-    BrightRed,
-    BrightGreen,
-    BrightYellow,
-    BrightBlue,
-    BrightMagenta,
-    BrightCyan,
-    BrightWhite,
-    Indexed(u8),
-    Rgb(u8, u8, u8),
-}
-
-#[derive(Debug, Clone, Default)]
-struct Attributes {
-    foreground: Color,
-    background: Color,
-    bold: bool,
-    italic: bool,
-    underline: bool,
-    inverse: bool,
-}
-
-#[derive(Debug, Clone)]
-enum TerminalEvent {
-    Print { char: char, attrs: Attributes },
-    Linefeed,
-    CarriageReturn,
-    Backspace,
-    Tab,
-    Bell,
-    CursorPosition { row: u16, col: u16 },
-    CursorUp(u16),
-    CursorDown(u16),
-    CursorForward(u16),
-    CursorBack(u16),
-    EraseDisplay(u16),
-    EraseLine(u16),
-    SetMode(Vec<u16>),
-    ResetMode(Vec<u16>),
-    UnhandledCsi { action: char, params: Vec<u16> },
-    UnhandledEsc(u8),
-    Osc(Vec<Vec<u8>>),
-}
-
-// Session 2 Part 2 - Parser
-
-struct Parser {
-    current_attrs: Attributes,
-    events: Vec<TerminalEvent>,
-}
-
-impl Parser {
-    fn new() -> Self {
-        Parser {
-            current_attrs: Attributes::default(),
-            events: Vec::new(),
-        }
-    }
-
-    fn handle_sgr(&mut self, params: &Params) {
-        let mut iter = params.iter().peekable();
-
-        // Reset when ESC[ with no params
-        if iter.peek().is_none() {
-            self.current_attrs = Attributes::default();
-            return;
-        }
-
-        for param in &mut iter {
-            match param {
-                [0] => self.current_attrs = Attributes::default(),
-                [1] => self.current_attrs.bold = true,
-                [3] => self.current_attrs.italic = true,
-                [4] => self.current_attrs.underline = true,
-                [7] => self.current_attrs.inverse = true,
-                [22] => self.current_attrs.bold = false,
-                [23] => self.current_attrs.italic = false,
-                [24] => self.current_attrs.underline = false,
-                [27] => self.current_attrs.inverse = false,
-                [30] => self.current_attrs.foreground = Color::Black,
-                [31] => self.current_attrs.foreground = Color::Red,
-                [32] => self.current_attrs.foreground = Color::Green,
-                [33] => self.current_attrs.foreground = Color::Yellow,
-                [34] => self.current_attrs.foreground = Color::Blue,
-                [35] => self.current_attrs.foreground = Color::Magenta,
-                [36] => self.current_attrs.foreground = Color::Cyan,
-                [37] => self.current_attrs.foreground = Color::White,
-                [38] => self.current_attrs.foreground = Color::Default,
-                [40] => self.current_attrs.background = Color::Black,
-                [41] => self.current_attrs.background = Color::Red,
-                [42] => self.current_attrs.background = Color::Green,
-                [43] => self.current_attrs.background = Color::Yellow,
-                [44] => self.current_attrs.background = Color::Blue,
-                [45] => self.current_attrs.background = Color::Magenta,
-                [46] => self.current_attrs.background = Color::Cyan,
-                [47] => self.current_attrs.background = Color::White,
-                [49] => self.current_attrs.background = Color::Default,
-                [90] => self.current_attrs.foreground = Color::BrightBlack,
-                [91] => self.current_attrs.foreground = Color::BrightRed,
-                [92] => self.current_attrs.foreground = Color::BrightGreen,
-                [93] => self.current_attrs.foreground = Color::BrightYellow,
-                [94] => self.current_attrs.foreground = Color::BrightBlue,
-                [95] => self.current_attrs.foreground = Color::BrightMagenta,
-                [96] => self.current_attrs.foreground = Color::BrightCyan,
-                [97] => self.current_attrs.foreground = Color::BrightWhite,
-                [38, 5, n] => self.current_attrs.foreground = Color::Indexed(*n as u8),
-                [38, 2, r, g, b] => {
-                    self.current_attrs.foreground = Color::Rgb(*r as u8, *g as u8, *b as u8)
-                }
-                [48, 5, n] => self.current_attrs.background = Color::Indexed(*n as u8),
-                [48, 2, r, g, b] => {
-                    self.current_attrs.background = Color::Rgb(*r as u8, *g as u8, *b as u8)
-                }
-                _ => {}
-            }
-        }
-    }
-}
-
-impl Perform for Parser {
-    fn print(&mut self, c: char) {
-        self.events.push(TerminalEvent::Print {
-            char: c,
-            attrs: self.current_attrs.clone(),
-        });
-    }
-
-    fn execute(&mut self, byte: u8) {
-        let event = match byte {
-            0x0A => TerminalEvent::Linefeed,
-            0x0D => TerminalEvent::CarriageReturn,
-            0x08 => TerminalEvent::Backspace,
-            0x09 => TerminalEvent::Tab,
-            0x07 => TerminalEvent::Bell,
-            _ => return,
-        };
-        self.events.push(event);
-    }
-
-    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], ignore: bool, action: char) {
-        if ignore {
-            return;
-        }
-
-        let event = match action {
-            'm' => {
-                self.handle_sgr(params);
-                return;
-            }
-            'H' | 'f' => {
-                // Cursor positions
-                let mut iter = params.iter();
-                let row = iter.next().and_then(|p| p.first()).copied().unwrap_or(1);
-                let col = iter.next().and_then(|p| p.first()).copied().unwrap_or(1);
-                TerminalEvent::CursorPosition { row, col }
-            }
-            'A' => {
-                let n = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(1);
-                TerminalEvent::CursorUp(n)
-            }
-            'B' => {
-                let n = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(1);
-                TerminalEvent::CursorDown(n)
-            }
-            'C' => {
-                let n = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(1);
-                TerminalEvent::CursorForward(n)
-            }
-            'D' => {
-                let n = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(1);
-                TerminalEvent::CursorBack(n)
-            }
-            'J' => {
-                // Cursor positions
-                let mode = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(0);
-                TerminalEvent::EraseDisplay(mode)
-            }
-            'K' => {
-                // Cursor positions
-                let mode = params
-                    .iter()
-                    .next()
-                    .and_then(|p| p.first())
-                    .copied()
-                    .unwrap_or(0);
-                TerminalEvent::EraseLine(mode)
-            }
-            'h' => {
-                // Mode set/reset - often used with ? prefix
-                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
-                TerminalEvent::SetMode(modes)
-            }
-            'l' => {
-                // Mode set/reset - often used with ? prefix
-                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
-                TerminalEvent::ResetMode(modes)
-            }
-            _ => {
-                let p: Vec<u16> = params.iter().flat_map(|p| p.to_vec()).collect();
-                TerminalEvent::UnhandledCsi { action, params: p }
-            }
-        };
-        self.events.push(event);
-    }
-
-    // Simple ESC sequences
-    // ESC followed by just one byte, without '['
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, byte: u8) {
-        self.events.push(TerminalEvent::UnhandledEsc(byte));
-    }
-
-    // Called for Operating System Commands
-    // ESC followed by ']'
-    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
-        let owned: Vec<Vec<u8>> = params.iter().map(|p| p.to_vec()).collect();
-        self.events.push(TerminalEvent::Osc(owned));
-    }
-
-    // hook, put, unhook Device Control String
-    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _action: char) {}
-
-    fn put(&mut self, _byte: u8) {}
-
-    fn unhook(&mut self) {}
-}
-
-// Session 3 - Terminal State Types
-
-#[derive(Debug, Clone)]
-struct Cell {
-    character: char,
-    attrs: Attributes,
-}
-
-impl Default for Cell {
-    fn default() -> Self {
-        Cell {
-            character: ' ',
-            attrs: Attributes::default(),
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct Cursor {
-    row: usize,
-    col: usize,
-}
-
-impl Default for Cursor {
-    fn default() -> Self {
-        Cursor { row: 0, col: 0 }
-    }
-}
-
-struct Terminal {
-    grid: Vec<Vec<Cell>>,
-    cursor: Cursor,
-    rows: usize,
-    cols: usize,
-}
-
-impl Terminal {
-    fn new(cols: usize, rows: usize) -> Self {
-        let grid = (0..rows)
-            .map(|_| (0..cols).map(|_| Cell::default()).collect())
-            .collect();
-
-        Terminal {
-            grid,
-            cursor: Cursor::default(),
-            rows,
-            cols,
-        }
-    }
-
-    fn print(&mut self, c: char, attrs: Attributes) {
-        self.grid[self.cursor.row][self.cursor.col] = Cell {
-            character: c,
-            attrs,
-        };
-        self.cursor.col += 1;
-        if self.cursor.col >= self.cols {
-            self.cursor.col = 0;
-            self.cursor.row += 1;
-            if self.cursor.row >= self.rows {
-                self.cursor.row = self.rows - 1;
-            }
-        }
-    }
-
-    fn set_cursor_position(&mut self, row: u16, col: u16) {
-        let row = if row == 0 { 1 } else { row };
-        let col = if col == 0 { 1 } else { col };
-
-        self.cursor.row = ((row - 1) as usize).min(self.rows - 1);
-        self.cursor.col = ((col - 1) as usize).min(self.cols - 1);
-    }
-
-    fn cursor_up(&mut self, n: u16) {
-        self.cursor.row = self.cursor.row.saturating_sub(n as usize);
-    }
-
-    fn cursor_down(&mut self, n: u16) {
-        self.cursor.row = (self.cursor.row + n as usize).min(self.rows - 1);
-    }
-
-    fn cursor_forward(&mut self, n: u16) {
-        self.cursor.col = (self.cursor.col + n as usize).min(self.cols - 1);
-    }
-
-    fn cursor_back(&mut self, n: u16) {
-        self.cursor.col = self.cursor.col.saturating_sub(n as usize);
-    }
-
-    fn carriage_return(&mut self) {
-        self.cursor.col = 0;
-    }
-
-    fn line_feed(&mut self) {
-        self.cursor.row = (self.cursor.row + 1).min(self.rows - 1);
-        self.cursor.col = 0;
-    }
-
-    fn tab(&mut self) {
-        let next_tab_stop = (self.cursor.col / 8 + 1) * 8;
-        self.cursor.col = next_tab_stop.min(self.cols - 1);
-    }
-
-    fn backspace(&mut self) {
-        self.cursor.col = self.cursor.col.saturating_sub(1);
-    }
-
-    fn clear_cell(&mut self, row: usize, col: usize) {
-        self.grid[row][col] = Cell::default();
-    }
-
-    fn erase_display(&mut self, mode: u16) {
-        match mode {
-            0 => {
-                for col in self.cursor.col..self.cols {
-                    self.clear_cell(self.cursor.row, col);
-                }
-                for row in (self.cursor.row + 1)..self.rows {
-                    for col in 0..self.cols {
-                        self.clear_cell(row, col);
-                    }
-                }
-            }
-            1 => {
-                for row in 0..self.cursor.row {
-                    for col in 0..self.cols {
-                        self.clear_cell(row, col);
-                    }
-                }
-                for col in 0..=self.cursor.col {
-                    self.clear_cell(self.cursor.row, col);
-                }
-            }
-            2 | 3 => {
-                for row in 0..self.rows {
-                    for col in 0..self.cols {
-                        self.clear_cell(row, col);
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
-
-    fn erase_line(&mut self, mode: u16) {
-        let row = self.cursor.row;
-        match mode {
-            0 => {
-                for col in self.cursor.col..self.cols {
-                    self.clear_cell(row, col);
-                }
-            }
-            1 => {
-                for col in 0..=self.cursor.col {
-                    self.clear_cell(row, col);
-                }
-            }
-            2 => {
-                for col in 0..self.cols {
-                    self.clear_cell(row, col);
-                }
-            }
-            _ => {}
-        }
-    }
-
-    fn process_event(&mut self, event: &TerminalEvent) {
-        match event {
-            TerminalEvent::Print { char, attrs } => {
-                self.print(*char, attrs.clone());
-            }
-            TerminalEvent::Linefeed => {
-                self.line_feed();
-            }
-            TerminalEvent::CarriageReturn => {
-                self.carriage_return();
-            }
-            TerminalEvent::Backspace => {
-                self.backspace();
-            }
-            TerminalEvent::Tab => {
-                self.tab();
-            }
-            TerminalEvent::Bell => {}
-            TerminalEvent::CursorPosition { row, col } => {
-                self.set_cursor_position(*row, *col);
-            }
-            TerminalEvent::CursorUp(n) => {
-                self.cursor_up(*n);
-            }
-            TerminalEvent::CursorDown(n) => {
-                self.cursor_down(*n);
-            }
-            TerminalEvent::CursorForward(n) => {
-                self.cursor_forward(*n);
-            }
-            TerminalEvent::CursorBack(n) => {
-                self.cursor_back(*n);
-            }
-            TerminalEvent::EraseDisplay(mode) => {
-                self.erase_display(*mode);
-            }
-            TerminalEvent::EraseLine(mode) => {
-                self.erase_line(*mode);
-            }
-            TerminalEvent::SetMode(_)
-            | TerminalEvent::ResetMode(_)
-            | TerminalEvent::UnhandledCsi { .. }
-            | TerminalEvent::UnhandledEsc(_)
-            | TerminalEvent::Osc(_) => {}
-        }
-    }
-
-    fn process_events(&mut self, events: &[TerminalEvent]) {
-        for event in events {
-            self.process_event(event);
-        }
-    }
-
-    fn render_content(&self) -> String {
-        let mut output = String::new();
-        for row in &self.grid {
-            for cell in row {
-                output.push(cell.character)
-            }
-            output.push('\n')
-        }
-        output
-    }
-
-    fn debug_render(&self) -> String {
-        let mut output = String::new();
-
-        for (row_idx, row) in self.grid.iter().enumerate() {
-            for (col_idx, cell) in row.iter().enumerate() {
-                if row_idx == self.cursor.row && col_idx == self.cursor.col {
-                    output.push('[');
-                    output.push(cell.character);
-                    output.push(']');
-                } else {
-                    output.push(cell.character);
-                }
-            }
-            output.push('\n');
-        }
-
-        output.push_str(&format!(
-            "Cursor: row={} col={}\n",
-            self.cursor.row, self.cursor.col
-        ));
-
-        output
-    }
-}
 
 fn main() -> Result<()> {
     println!("=== Full Integration Test ===\n");
@@ -538,15 +18,13 @@ fn main() -> Result<()> {
     })?;
 
     let mut cmd = CommandBuilder::new("/bin/bash");
-    cmd.args(&["--norc", "--noprofile", "-i"]);
+    cmd.args(["--norc", "--noprofile", "-i"]);
     let mut child = pair.slave.spawn_command(cmd)?;
 
     let mut reader = pair.master.try_clone_reader()?;
     let mut writer = pair.master.take_writer()?;
 
-    // Set up parser and terminal
-    let mut vte_parser = vte::Parser::new();
-    let mut handler = Parser::new();
+    // Set up terminal
     let mut terminal = Terminal::new(80, 24);
 
     // Send a command
@@ -558,8 +36,10 @@ fn main() -> Result<()> {
     let mut buffer = [0u8; 4096];
     let n = reader.read(&mut buffer)?;
 
-    vte_parser.advance(&mut handler, &buffer[..n]);
-    terminal.process_events(&handler.events);
+    terminal.feed(&buffer[..n]);
+
+    // Answer any queries (DSR, ...) the shell sent
+    writer.write_all(&terminal.take_responses())?;
 
     // Display results
     println!("Terminal state:");
@@ -0,0 +1,1699 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use vte::{Params, Perform};
+
+// Session 2 Part 1 - Color, Attributes, Events
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash)]
+pub enum Color {
+    #[default]
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// SGR 4:n underline shapes. `Attributes::underline` stays the on/off switch
+// renderers check; the style only refines how it is drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash)]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Hash)]
+pub struct Attributes {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub underline_style: UnderlineStyle,
+    // SGR 58; Default means "same as the text"
+    pub underline_color: Color,
+}
+
+impl Attributes {
+    /// Applies an SGR parameter list on top of `base`, one slice per
+    /// semicolon-separated parameter holding its colon subparameters, as
+    /// `vte::Params` yields them: `38;5;200` is `[[38], [5], [200]]` and
+    /// `38:5:200` is `[[38, 5, 200]]`.
+    /// An empty list resets to the default pen, as `ESC [ m` does.
+    /// A 38/48 introducer missing its index or channels (`38;5`, `38;2;255`)
+    /// is dropped and leaves the color as it was.
+    pub fn from_sgr(params: &[&[u16]], base: Attributes) -> Attributes {
+        Self::from_sgr_with(params, base, |_| {})
+    }
+
+    // `from_sgr`, handing each unrecognised parameter to `unhandled`
+    fn from_sgr_with(
+        params: &[&[u16]],
+        base: Attributes,
+        mut unhandled: impl FnMut(&[u16]),
+    ) -> Attributes {
+        if params.is_empty() {
+            return Attributes::default();
+        }
+        let mut attrs = base;
+        let mut iter = params.iter().copied();
+        while let Some(param) = iter.next() {
+            if !attrs.apply_sgr(param, &mut iter) {
+                unhandled(param);
+            }
+        }
+        attrs
+    }
+
+    // Applies one SGR parameter (with any colon subparams), taking a
+    // semicolon-form color spec from `rest`. False if it is not recognised.
+    fn apply_sgr<'a>(&mut self, param: &[u16], rest: &mut impl Iterator<Item = &'a [u16]>) -> bool {
+        match param {
+            [0] => *self = Attributes::default(),
+            [1] => self.bold = true,
+            [3] => self.italic = true,
+            [4] => self.set_underline(UnderlineStyle::Single),
+            [4, style] => {
+                let style = match style {
+                    0 => UnderlineStyle::None,
+                    1 => UnderlineStyle::Single,
+                    2 => UnderlineStyle::Double,
+                    3 => UnderlineStyle::Curly,
+                    4 => UnderlineStyle::Dotted,
+                    5 => UnderlineStyle::Dashed,
+                    _ => return false,
+                };
+                self.set_underline(style);
+            }
+            [7] => self.inverse = true,
+            [21] => self.set_underline(UnderlineStyle::Double),
+            [22] => self.bold = false,
+            [23] => self.italic = false,
+            // 24 ends the underline but keeps its color; only 59 resets that
+            [24] => self.set_underline(UnderlineStyle::None),
+            [27] => self.inverse = false,
+            [30] => self.foreground = Color::Black,
+            [31] => self.foreground = Color::Red,
+            [32] => self.foreground = Color::Green,
+            [33] => self.foreground = Color::Yellow,
+            [34] => self.foreground = Color::Blue,
+            [35] => self.foreground = Color::Magenta,
+            [36] => self.foreground = Color::Cyan,
+            [37] => self.foreground = Color::White,
+            // Semicolon form: the color spec follows as separate params.
+            // A bare or truncated introducer leaves the pen unchanged.
+            [38] => {
+                if let Some(color) = extended_color(rest) {
+                    self.foreground = color;
+                }
+            }
+            [39] => self.foreground = Color::Default,
+            [40] => self.background = Color::Black,
+            [41] => self.background = Color::Red,
+            [42] => self.background = Color::Green,
+            [43] => self.background = Color::Yellow,
+            [44] => self.background = Color::Blue,
+            [45] => self.background = Color::Magenta,
+            [46] => self.background = Color::Cyan,
+            [47] => self.background = Color::White,
+            [48] => {
+                if let Some(color) = extended_color(rest) {
+                    self.background = color;
+                }
+            }
+            [49] => self.background = Color::Default,
+            [58] => {
+                if let Some(color) = extended_color(rest) {
+                    self.underline_color = color;
+                }
+            }
+            [59] => self.underline_color = Color::Default,
+            [90] => self.foreground = Color::BrightBlack,
+            [91] => self.foreground = Color::BrightRed,
+            [92] => self.foreground = Color::BrightGreen,
+            [93] => self.foreground = Color::BrightYellow,
+            [94] => self.foreground = Color::BrightBlue,
+            [95] => self.foreground = Color::BrightMagenta,
+            [96] => self.foreground = Color::BrightCyan,
+            [97] => self.foreground = Color::BrightWhite,
+            [100] => self.background = Color::BrightBlack,
+            [101] => self.background = Color::BrightRed,
+            [102] => self.background = Color::BrightGreen,
+            [103] => self.background = Color::BrightYellow,
+            [104] => self.background = Color::BrightBlue,
+            [105] => self.background = Color::BrightMagenta,
+            [106] => self.background = Color::BrightCyan,
+            [107] => self.background = Color::BrightWhite,
+            // Colon forms, with or without the ITU color space id (`38:2::r:g:b`)
+            [38, 5, n] => self.foreground = Color::Indexed(clamp_u8(*n)),
+            [38, 2, r, g, b] | [38, 2, _, r, g, b] => {
+                self.foreground = Color::Rgb(clamp_u8(*r), clamp_u8(*g), clamp_u8(*b))
+            }
+            [48, 5, n] => self.background = Color::Indexed(clamp_u8(*n)),
+            [48, 2, r, g, b] | [48, 2, _, r, g, b] => {
+                self.background = Color::Rgb(clamp_u8(*r), clamp_u8(*g), clamp_u8(*b))
+            }
+            [58, 5, n] => self.underline_color = Color::Indexed(clamp_u8(*n)),
+            [58, 2, r, g, b] | [58, 2, _, r, g, b] => {
+                self.underline_color = Color::Rgb(clamp_u8(*r), clamp_u8(*g), clamp_u8(*b))
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn underline_code(&self) -> u16 {
+        if self.underline_style == UnderlineStyle::Double {
+            21
+        } else {
+            4
+        }
+    }
+
+    fn set_underline(&mut self, style: UnderlineStyle) {
+        self.underline = style != UnderlineStyle::None;
+        self.underline_style = style;
+    }
+
+    // A fully default pen needs no SGR at all when rendering
+    pub fn is_default(&self) -> bool {
+        *self == Attributes::default()
+    }
+
+    // SGR parameters that recreate this pen from a reset, without the leading 0.
+    // Plain semicolon params cannot carry 4:n, so styles other than double
+    // (SGR 21) come out as a single underline.
+    pub(crate) fn sgr_params(&self) -> Vec<u16> {
+        let mut params = Vec::new();
+        for (set, code) in [
+            (self.bold, 1),
+            (self.italic, 3),
+            (self.underline, self.underline_code()),
+            (self.inverse, 7),
+        ] {
+            if set {
+                params.push(code);
+            }
+        }
+        push_color_params(&mut params, self.foreground, 30);
+        push_color_params(&mut params, self.background, 40);
+        push_color_params(&mut params, self.underline_color, 50);
+        params
+    }
+
+    // Shortest SGR parameter list that turns the `from` pen into this one:
+    // individual off codes (22/23/24/27/39/49) unless everything goes back to default
+    pub fn sgr_transition(&self, from: &Attributes) -> Vec<u16> {
+        if self == from {
+            return Vec::new();
+        }
+        if self.is_default() {
+            return vec![0];
+        }
+
+        let mut params = Vec::new();
+        for (now, before, on, off) in [
+            (self.bold, from.bold, 1, 22),
+            (self.italic, from.italic, 3, 23),
+            (self.underline, from.underline, self.underline_code(), 24),
+            (self.inverse, from.inverse, 7, 27),
+        ] {
+            if now != before {
+                params.push(if now { on } else { off });
+            }
+        }
+        if self.underline && from.underline && self.underline_code() != from.underline_code() {
+            params.push(self.underline_code());
+        }
+        for (now, before, base) in [
+            (self.foreground, from.foreground, 30),
+            (self.background, from.background, 40),
+            (self.underline_color, from.underline_color, 50),
+        ] {
+            if now == before {
+                continue;
+            }
+            if now == Color::Default {
+                params.push(base + 9);
+            } else {
+                push_color_params(&mut params, now, base);
+            }
+        }
+        params
+    }
+
+    // Builder-style setters for styling programmatic output
+    pub fn with_fg(self, foreground: Color) -> Self {
+        Attributes { foreground, ..self }
+    }
+
+    pub fn with_bg(self, background: Color) -> Self {
+        Attributes { background, ..self }
+    }
+
+    pub fn with_bold(self, bold: bool) -> Self {
+        Attributes { bold, ..self }
+    }
+
+    pub fn with_italic(self, italic: bool) -> Self {
+        Attributes { italic, ..self }
+    }
+
+    pub fn with_underline(self, underline: bool) -> Self {
+        let style = if underline {
+            UnderlineStyle::Single
+        } else {
+            UnderlineStyle::None
+        };
+        self.with_underline_style(style)
+    }
+
+    pub fn with_underline_style(mut self, style: UnderlineStyle) -> Self {
+        self.set_underline(style);
+        self
+    }
+
+    pub fn with_underline_color(self, underline_color: Color) -> Self {
+        Attributes {
+            underline_color,
+            ..self
+        }
+    }
+
+    pub fn with_inverse(self, inverse: bool) -> Self {
+        Attributes { inverse, ..self }
+    }
+}
+
+// Color indices and channels above 255 are malformed; saturate instead of
+// letting a cast wrap them (300 would otherwise become 44)
+fn clamp_u8(n: u16) -> u8 {
+    n.min(u8::MAX as u16) as u8
+}
+
+// Reads `5;n` or `2;r;g;b` after a 38/48 introducer
+fn extended_color<'a>(iter: &mut impl Iterator<Item = &'a [u16]>) -> Option<Color> {
+    let mut next = || iter.next().and_then(|p| p.first()).copied();
+    match next()? {
+        5 => Some(Color::Indexed(clamp_u8(next()?))),
+        2 => Some(Color::Rgb(
+            clamp_u8(next()?),
+            clamp_u8(next()?),
+            clamp_u8(next()?),
+        )),
+        _ => None,
+    }
+}
+
+// X11 color specs as used by OSC 4: `rgb:r/g/b` with 1-4 hex digits per
+// channel, or `#rrggbb`
+fn parse_color_spec(spec: &[u8]) -> Option<(u8, u8, u8)> {
+    let spec = core::str::from_utf8(spec).ok()?;
+    if let Some(channels) = spec.strip_prefix("rgb:") {
+        let mut channels = channels.split('/').map(|channel| {
+            if channel.is_empty() || channel.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(channel, 16).ok()?;
+            let max = (1u32 << (4 * channel.len())) - 1;
+            Some((value * 255 / max) as u8)
+        });
+        let rgb = (channels.next()??, channels.next()??, channels.next()??);
+        return channels.next().is_none().then_some(rgb);
+    }
+
+    let hex = spec.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+// OSC 1337 ; File=key=value;...:base64 ST, with vte having already split
+// the arguments on ';'
+fn inline_image(params: &[&[u8]]) -> Option<TerminalEvent> {
+    let joined = params.join(&b';');
+    let file = joined.strip_prefix(b"File=")?;
+    let colon = file.iter().position(|&b| b == b':')?;
+    let (header, payload) = (&file[..colon], &file[colon + 1..]);
+
+    let args = header
+        .split(|&b| b == b';')
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| {
+            let text = String::from_utf8_lossy(arg);
+            match text.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (text.into_owned(), String::new()),
+            }
+        })
+        .collect();
+    let data = decode_base64(payload)?;
+    Some(TerminalEvent::InlineImage { args, data })
+}
+
+// Standard-alphabet base64; whitespace is skipped and padding is optional
+fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in input {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+// `base` is 30 for foreground, 40 for background and 50 for the underline
+// color; Default adds nothing
+fn push_color_params(params: &mut Vec<u16>, color: Color, base: u16) {
+    let n = match color {
+        Color::Default => return,
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::White => 7,
+        Color::BrightBlack => 8,
+        Color::BrightRed => 9,
+        Color::BrightGreen => 10,
+        Color::BrightYellow => 11,
+        Color::BrightBlue => 12,
+        Color::BrightMagenta => 13,
+        Color::BrightCyan => 14,
+        Color::BrightWhite => 15,
+        Color::Indexed(n) => return params.extend([base + 8, 5, n as u16]),
+        Color::Rgb(r, g, b) => return params.extend([base + 8, 2, r as u16, g as u16, b as u16]),
+    };
+    // The underline color (base 50, i.e. SGR 58) has no short named forms
+    if base == 50 {
+        params.extend([58, 5, n]);
+    } else if n < 8 {
+        params.push(base + n);
+    } else {
+        params.push(base + 60 + n - 8);
+    }
+}
+
+// DECDHL / DECDWL / DECSWL per-row rendering size
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineAttribute {
+    #[default]
+    SingleWidth,
+    DoubleWidth,
+    DoubleHeightTop,
+    DoubleHeightBottom,
+}
+
+// Rectangle corners as sent by VT420 rectangle operations: 1-based and
+// inclusive, with 0 standing for the far edge of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub top: u16,
+    pub left: u16,
+    pub bottom: u16,
+    pub right: u16,
+}
+
+impl Rect {
+    fn from_params(params: &[u16]) -> Self {
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        Rect {
+            top: param(0),
+            left: param(1),
+            bottom: param(2),
+            right: param(3),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadKind {
+    Osc,
+    Dcs,
+    // OSC 1337 File=, which has its own, larger limit
+    InlineImage,
+}
+
+#[derive(Debug, Clone)]
+pub enum TerminalEvent {
+    Print {
+        char: char,
+        attrs: Attributes,
+    },
+    PrintStr {
+        text: String,
+        attrs: Attributes,
+    },
+    Linefeed,
+    CarriageReturn,
+    Backspace,
+    Tab,
+    Bell,
+    Enquiry,
+    CursorPosition {
+        row: u16,
+        col: u16,
+    },
+    CursorUp(u16),
+    CursorDown(u16),
+    CursorForward(u16),
+    CursorBack(u16),
+    CursorColumn(u16),
+    EraseDisplay(u16),
+    EraseLine(u16),
+    SetMode(Vec<u16>),
+    ResetMode(Vec<u16>),
+    SetPrivateMode(Vec<u16>),
+    ResetPrivateMode(Vec<u16>),
+    UnhandledCsi {
+        action: char,
+        params: Vec<u16>,
+    },
+    UnhandledEsc(u8),
+    UnhandledSgr(Vec<u16>),
+    // XTMODKEYS; a missing value resets that resource. vte reports a bare
+    // `CSI > m` as resource 0, so there is no separate reset-all form.
+    ModifyKeys {
+        resource: u16,
+        value: Option<u16>,
+    },
+    // bell_terminated records BEL vs ST so a proxy can re-encode faithfully
+    Osc {
+        params: Vec<Vec<u8>>,
+        bell_terminated: bool,
+    },
+    AlignmentTest,
+    // DECKPAM (ESC =) when true, DECKPNM (ESC >) when false
+    ApplicationKeypad(bool),
+    LineAttribute(LineAttribute),
+    Dcs {
+        params: Vec<u16>,
+        intermediates: Vec<u8>,
+        action: char,
+        data: Vec<u8>,
+    },
+    OversizedPayload {
+        kind: PayloadKind,
+        limit: usize,
+    },
+    SetPointerShape(String),
+    // OSC 0 / OSC 2 window title
+    SetTitle(String),
+    // OSC 8 ; params ; URI - an empty URI ends the current link
+    Hyperlink {
+        id: Option<String>,
+        uri: String,
+    },
+    // OSC 104; an empty list resets the whole palette
+    ResetPaletteColors(Vec<u8>),
+    // OSC 4 ; index ; spec - one event per index/spec pair
+    SetPaletteColor {
+        index: u8,
+        rgb: (u8, u8, u8),
+    },
+    // iTerm2 OSC 1337 File=; args keep their raw values, data is decoded.
+    // Payloads past `Parser::set_max_image_payload` (16 MiB by default)
+    // arrive as OversizedPayload with kind InlineImage instead.
+    InlineImage {
+        args: BTreeMap<String, String>,
+        data: Vec<u8>,
+    },
+    InsertChars(u16),
+    Repeat(u16),
+    DeleteChars(u16),
+    // CSI s is DECSLRM while DECLRMM is on and SCOSC otherwise; only the terminal knows which
+    SetMarginsOrSaveCursor {
+        left: u16,
+        right: u16,
+    },
+    RestoreCursor,
+    SavePrivateModes(Vec<u16>),
+    RestorePrivateModes(Vec<u16>),
+    SetScrollRegion {
+        top: u16,
+        bottom: u16,
+    },
+    DeviceStatusReport(u16),
+    DeviceAttributes,
+    SecondaryDeviceAttributes,
+    TertiaryDeviceAttributes,
+    // DECRQSS; the pen travels along because only the parser tracks it
+    RequestStatus {
+        setting: Vec<u8>,
+        attrs: Attributes,
+    },
+    // XTGETTCAP; the still hex-encoded, ';'-separated capability names
+    RequestTermcap(Vec<u8>),
+    EraseRect(Rect),
+    // Destination corner is 1-based like the source rectangle; pages are ignored
+    CopyRect {
+        src: Rect,
+        top: u16,
+        left: u16,
+    },
+    FillRect {
+        character: char,
+        attrs: Attributes,
+        rect: Rect,
+    },
+    SetCursorStyle(u16),
+    WindowOp(Vec<u16>),
+    SoftReset,
+    SetTabStop,
+    ClearTabStops(u16),
+    ResetTabStops,
+}
+
+// Session 2 Part 2 - Parser
+
+type EventHook = Box<dyn FnMut(&TerminalEvent) + Send>;
+
+// Upper bound on a single OSC or DCS payload before it is dropped
+const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+// Inline images routinely run past the generic cap, so OSC 1337 File= gets its own
+const DEFAULT_MAX_IMAGE_PAYLOAD: usize = 16 * 1024 * 1024;
+
+// Leading OSC bytes that mark an inline image
+const INLINE_IMAGE_PREFIX: &[u8] = b"1337;File=";
+
+// Where the byte stream sits relative to an OSC string. vte buffers OSC
+// payloads until the terminator, so the length has to be tracked up front.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum OscScan {
+    #[default]
+    Ground,
+    Escape,
+    // `image` holds while the payload still matches INLINE_IMAGE_PREFIX
+    Osc {
+        len: usize,
+        image: bool,
+    },
+    Discard,
+    DiscardEscape,
+}
+
+// Plain counters for profiling what a workload spends its bytes on
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ParserStats {
+    pub bytes: usize,
+    pub prints: usize,
+    pub executes: usize,
+    pub csi_dispatches: usize,
+    pub osc_dispatches: usize,
+}
+
+struct PendingDcs {
+    params: Vec<u16>,
+    intermediates: Vec<u8>,
+    action: char,
+    data: Vec<u8>,
+    overflowed: bool,
+}
+
+pub struct Parser {
+    current_attrs: Attributes,
+    pub events: Vec<TerminalEvent>,
+    // Development hook fired for every unhandled CSI, ESC, or SGR sequence
+    on_unhandled: Option<EventHook>,
+    // Coalesce consecutive same-attribute prints into PrintStr runs
+    batch_prints: bool,
+    max_payload: usize,
+    max_image_payload: usize,
+    osc_scan: OscScan,
+    dropping_osc: bool,
+    dcs: Option<PendingDcs>,
+    stats: ParserStats,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            current_attrs: Attributes::default(),
+            events: Vec::new(),
+            on_unhandled: None,
+            batch_prints: false,
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            max_image_payload: DEFAULT_MAX_IMAGE_PAYLOAD,
+            osc_scan: OscScan::default(),
+            dropping_osc: false,
+            dcs: None,
+            stats: ParserStats::default(),
+        }
+    }
+
+    pub fn set_on_unhandled(&mut self, hook: impl FnMut(&TerminalEvent) + Send + 'static) {
+        self.on_unhandled = Some(Box::new(hook));
+    }
+
+    pub fn set_batch_prints(&mut self, enabled: bool) {
+        self.batch_prints = enabled;
+    }
+
+    pub fn stats(&self) -> ParserStats {
+        self.stats
+    }
+
+    pub fn set_max_payload(&mut self, limit: usize) {
+        self.max_payload = limit;
+    }
+
+    pub fn set_max_image_payload(&mut self, limit: usize) {
+        self.max_image_payload = limit;
+    }
+
+    // Feed bytes through vte, cutting off any OSC string that grows past
+    // `max_payload` (`max_image_payload` for inline images) so an
+    // unterminated one cannot buffer forever
+    pub fn advance(&mut self, vte: &mut vte::Parser, bytes: &[u8]) {
+        self.stats.bytes += bytes.len();
+        let mut start = 0;
+
+        for (i, &byte) in bytes.iter().enumerate() {
+            if self.osc_scan == OscScan::DiscardEscape {
+                if byte == b'\\' {
+                    // ST closing the dropped string
+                    self.osc_scan = OscScan::Ground;
+                    start = i + 1;
+                    continue;
+                }
+                // Any other escape ends the string and starts a new sequence
+                vte.advance(self, &[0x1B]);
+                self.osc_scan = OscScan::Escape;
+                start = i;
+            }
+
+            self.osc_scan = match (self.osc_scan, byte) {
+                (OscScan::Discard, 0x07 | 0x18 | 0x1A) => {
+                    start = i + 1;
+                    OscScan::Ground
+                }
+                (OscScan::Discard, 0x1B) => {
+                    start = i + 1;
+                    OscScan::DiscardEscape
+                }
+                (OscScan::Discard, _) => {
+                    start = i + 1;
+                    OscScan::Discard
+                }
+                (OscScan::Osc { .. }, 0x07 | 0x18 | 0x1A) => OscScan::Ground,
+                (_, 0x1B) => OscScan::Escape,
+                (OscScan::Escape, b']') => OscScan::Osc {
+                    len: 0,
+                    image: true,
+                },
+                (OscScan::Osc { len, image }, _) if len >= self.osc_limit(image) => {
+                    // CAN aborts the string inside vte; skip the rest ourselves
+                    vte.advance(self, &bytes[start..i]);
+                    self.dropping_osc = true;
+                    vte.advance(self, &[0x18]);
+                    self.dropping_osc = false;
+                    self.events.push(TerminalEvent::OversizedPayload {
+                        kind: if image {
+                            PayloadKind::InlineImage
+                        } else {
+                            PayloadKind::Osc
+                        },
+                        limit: self.osc_limit(image),
+                    });
+                    start = i + 1;
+                    OscScan::Discard
+                }
+                (OscScan::Osc { len, image }, _) => OscScan::Osc {
+                    len: len + 1,
+                    image: image && INLINE_IMAGE_PREFIX.get(len).is_none_or(|&b| b == byte),
+                },
+                _ => OscScan::Ground,
+            };
+        }
+
+        vte.advance(self, &bytes[start..]);
+    }
+
+    fn osc_limit(&self, image: bool) -> usize {
+        if image {
+            self.max_image_payload
+        } else {
+            self.max_payload
+        }
+    }
+
+    fn push_unhandled(&mut self, event: TerminalEvent) {
+        if let Some(hook) = self.on_unhandled.as_mut() {
+            hook(&event);
+        }
+        self.events.push(event);
+    }
+
+    fn handle_sgr(&mut self, params: &Params) {
+        let params: Vec<&[u16]> = params.iter().collect();
+        let mut unhandled = Vec::new();
+        self.current_attrs =
+            Attributes::from_sgr_with(&params, self.current_attrs.clone(), |param| {
+                unhandled.push(param.to_vec())
+            });
+        for param in unhandled {
+            self.push_unhandled(TerminalEvent::UnhandledSgr(param));
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Perform for Parser {
+    fn print(&mut self, c: char) {
+        self.stats.prints += 1;
+        if self.batch_prints {
+            if let Some(TerminalEvent::PrintStr { text, attrs }) = self.events.last_mut()
+                && *attrs == self.current_attrs
+            {
+                text.push(c);
+                return;
+            }
+            self.events.push(TerminalEvent::PrintStr {
+                text: c.to_string(),
+                attrs: self.current_attrs.clone(),
+            });
+            return;
+        }
+
+        self.events.push(TerminalEvent::Print {
+            char: c,
+            attrs: self.current_attrs.clone(),
+        });
+    }
+
+    fn execute(&mut self, byte: u8) {
+        // The CAN used to abort an oversized OSC is ours, not the application's
+        if self.dropping_osc {
+            return;
+        }
+        self.stats.executes += 1;
+
+        let event = match byte {
+            // VT and FF act as LF, as on xterm
+            0x0A..=0x0C => TerminalEvent::Linefeed,
+            0x0D => TerminalEvent::CarriageReturn,
+            0x08 => TerminalEvent::Backspace,
+            0x09 => TerminalEvent::Tab,
+            0x07 => TerminalEvent::Bell,
+            0x05 => TerminalEvent::Enquiry,
+            _ => return,
+        };
+        self.events.push(event);
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        self.stats.csi_dispatches += 1;
+        if ignore {
+            return;
+        }
+
+        // DEC private sequences carry a '?' prefix
+        let private = intermediates.first() == Some(&b'?');
+
+        let event = match action {
+            // Only a bare CSI m is SGR; prefixed forms must not touch the pen
+            'm' if intermediates.is_empty() => {
+                self.handle_sgr(params);
+                return;
+            }
+            // XTMODKEYS - CSI > Pp ; Pv m
+            'm' if intermediates == b">" => {
+                let mut iter = params.iter().map(|p| p.first().copied().unwrap_or(0));
+                TerminalEvent::ModifyKeys {
+                    resource: iter.next().unwrap_or(0),
+                    value: iter.next(),
+                }
+            }
+            'H' | 'f' => {
+                // Cursor positions
+                let mut iter = params.iter();
+                let row = iter.next().and_then(|p| p.first()).copied().unwrap_or(1);
+                let col = iter.next().and_then(|p| p.first()).copied().unwrap_or(1);
+                TerminalEvent::CursorPosition { row, col }
+            }
+            'A' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::CursorUp(n)
+            }
+            'B' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::CursorDown(n)
+            }
+            'C' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::CursorForward(n)
+            }
+            'D' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::CursorBack(n)
+            }
+            // CHA / HPA - absolute column
+            'G' | '`' => {
+                let col = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::CursorColumn(col)
+            }
+            'J' => {
+                // Cursor positions
+                let mode = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0);
+                TerminalEvent::EraseDisplay(mode)
+            }
+            'K' => {
+                // Cursor positions
+                let mode = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0);
+                TerminalEvent::EraseLine(mode)
+            }
+            '@' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::InsertChars(n)
+            }
+            // REP - repeat the preceding graphic character
+            'b' if !private => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::Repeat(n.max(1))
+            }
+            'P' => {
+                let n = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(1);
+                TerminalEvent::DeleteChars(n)
+            }
+            's' if !private => {
+                let mut iter = params.iter();
+                let left = iter.next().and_then(|p| p.first()).copied().unwrap_or(0);
+                let right = iter.next().and_then(|p| p.first()).copied().unwrap_or(0);
+                TerminalEvent::SetMarginsOrSaveCursor { left, right }
+            }
+            'u' if !private => TerminalEvent::RestoreCursor,
+            // XTSAVE / XTRESTORE - CSI ? Pm s / CSI ? Pm r
+            's' if private => {
+                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
+                TerminalEvent::SavePrivateModes(modes)
+            }
+            'r' if private => {
+                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
+                TerminalEvent::RestorePrivateModes(modes)
+            }
+            't' if !private => {
+                let p: Vec<u16> = params.iter().flat_map(|p| p.to_vec()).collect();
+                TerminalEvent::WindowOp(p)
+            }
+            // DECSTR - CSI ! p; the pen is parser state, so reset it here
+            'p' if intermediates == b"!" => {
+                self.current_attrs = Attributes::default();
+                TerminalEvent::SoftReset
+            }
+            // DECSCUSR - CSI Ps SP q
+            'q' if intermediates == b" " => {
+                let style = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0);
+                TerminalEvent::SetCursorStyle(style)
+            }
+            'r' if !private => {
+                let mut iter = params.iter();
+                let top = iter.next().and_then(|p| p.first()).copied().unwrap_or(0);
+                let bottom = iter.next().and_then(|p| p.first()).copied().unwrap_or(0);
+                TerminalEvent::SetScrollRegion { top, bottom }
+            }
+            // TBC - CSI Ps g
+            'g' if !private => {
+                let mode = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0);
+                TerminalEvent::ClearTabStops(mode)
+            }
+            // DECERA - CSI Pt;Pl;Pb;Pr $ z
+            'z' if intermediates == b"$" => {
+                let p: Vec<u16> = params
+                    .iter()
+                    .map(|p| p.first().copied().unwrap_or(0))
+                    .collect();
+                TerminalEvent::EraseRect(Rect::from_params(&p))
+            }
+            // DECCRA - CSI Pts;Pls;Pbs;Prs;Pps;Ptd;Pld;Ppd $ v
+            'v' if intermediates == b"$" => {
+                let p: Vec<u16> = params
+                    .iter()
+                    .map(|p| p.first().copied().unwrap_or(0))
+                    .collect();
+                let param = |i: usize| p.get(i).copied().unwrap_or(0);
+                TerminalEvent::CopyRect {
+                    src: Rect::from_params(&p),
+                    top: param(5),
+                    left: param(6),
+                }
+            }
+            // DECFRA - CSI Pch;Pt;Pl;Pb;Pr $ x, filled with the current pen
+            'x' if intermediates == b"$" => {
+                let p: Vec<u16> = params
+                    .iter()
+                    .map(|p| p.first().copied().unwrap_or(0))
+                    .collect();
+                let code = p.first().copied().unwrap_or(0);
+                let Some(character) = char::from_u32(code as u32).filter(|c| !c.is_control())
+                else {
+                    self.push_unhandled(TerminalEvent::UnhandledCsi { action, params: p });
+                    return;
+                };
+                TerminalEvent::FillRect {
+                    character,
+                    attrs: self.current_attrs.clone(),
+                    rect: Rect::from_params(p.get(1..).unwrap_or_default()),
+                }
+            }
+            // Primary DA - CSI c / CSI 0c
+            'c' if intermediates.is_empty() => TerminalEvent::DeviceAttributes,
+            // Secondary DA - CSI > c
+            'c' if intermediates == b">" => TerminalEvent::SecondaryDeviceAttributes,
+            // Tertiary DA - CSI = c
+            'c' if intermediates == b"=" => TerminalEvent::TertiaryDeviceAttributes,
+            // DECST8C - CSI ? 5 W
+            'W' if private && params.iter().next() == Some(&[5][..]) => {
+                TerminalEvent::ResetTabStops
+            }
+            'n' if !private => {
+                let code = params
+                    .iter()
+                    .next()
+                    .and_then(|p| p.first())
+                    .copied()
+                    .unwrap_or(0);
+                TerminalEvent::DeviceStatusReport(code)
+            }
+            'h' => {
+                // Mode set/reset - often used with ? prefix
+                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
+                if private {
+                    TerminalEvent::SetPrivateMode(modes)
+                } else {
+                    TerminalEvent::SetMode(modes)
+                }
+            }
+            'l' => {
+                // Mode set/reset - often used with ? prefix
+                let modes: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
+                if private {
+                    TerminalEvent::ResetPrivateMode(modes)
+                } else {
+                    TerminalEvent::ResetMode(modes)
+                }
+            }
+            _ => {
+                let p: Vec<u16> = params.iter().flat_map(|p| p.to_vec()).collect();
+                self.push_unhandled(TerminalEvent::UnhandledCsi { action, params: p });
+                return;
+            }
+        };
+        self.events.push(event);
+    }
+
+    // Simple ESC sequences
+    // ESC followed by just one byte, without '['
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        let event = match (intermediates, byte) {
+            // DECDHL / DECSWL / DECDWL - ESC # 3..6
+            ([b'#'], b'3') => TerminalEvent::LineAttribute(LineAttribute::DoubleHeightTop),
+            ([b'#'], b'4') => TerminalEvent::LineAttribute(LineAttribute::DoubleHeightBottom),
+            ([b'#'], b'5') => TerminalEvent::LineAttribute(LineAttribute::SingleWidth),
+            ([b'#'], b'6') => TerminalEvent::LineAttribute(LineAttribute::DoubleWidth),
+            // DECALN - ESC # 8
+            ([b'#'], b'8') => TerminalEvent::AlignmentTest,
+            // HTS - ESC H
+            ([], b'H') => TerminalEvent::SetTabStop,
+            ([], b'=') => TerminalEvent::ApplicationKeypad(true),
+            ([], b'>') => TerminalEvent::ApplicationKeypad(false),
+            _ => {
+                self.push_unhandled(TerminalEvent::UnhandledEsc(byte));
+                return;
+            }
+        };
+        self.events.push(event);
+    }
+
+    // Called for Operating System Commands
+    // ESC followed by ']'
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        if self.dropping_osc {
+            return;
+        }
+        self.stats.osc_dispatches += 1;
+
+        if let [b"22", shape] = params {
+            let shape = String::from_utf8_lossy(shape).into_owned();
+            self.events.push(TerminalEvent::SetPointerShape(shape));
+            return;
+        }
+
+        // The title may itself contain semicolons, which arrive as extra params
+        if let [b"0" | b"2", title @ ..] = params {
+            let title = title.join(&b';');
+            self.events.push(TerminalEvent::SetTitle(
+                String::from_utf8_lossy(&title).into_owned(),
+            ));
+            return;
+        }
+
+        // `id=` is the only key in the params field that means anything to us
+        if let [b"8", link_params, uri @ ..] = params {
+            let id = link_params
+                .split(|&b| b == b':')
+                .find_map(|param| param.strip_prefix(b"id="))
+                .map(|id| String::from_utf8_lossy(id).into_owned());
+            let uri = String::from_utf8_lossy(&uri.join(&b';')).into_owned();
+            self.events.push(TerminalEvent::Hyperlink { id, uri });
+            return;
+        }
+
+        if let [b"4", pairs @ ..] = params {
+            let colors: Vec<TerminalEvent> = pairs
+                .chunks_exact(2)
+                .filter_map(|pair| {
+                    let index = core::str::from_utf8(pair[0]).ok()?.parse().ok()?;
+                    let rgb = parse_color_spec(pair[1])?;
+                    Some(TerminalEvent::SetPaletteColor { index, rgb })
+                })
+                .collect();
+            if !colors.is_empty() {
+                self.events.extend(colors);
+                return;
+            }
+        }
+
+        if let [b"104", indices @ ..] = params {
+            let requested: Vec<&[u8]> = indices.iter().copied().filter(|i| !i.is_empty()).collect();
+            let indices: Vec<u8> = requested
+                .iter()
+                .filter_map(|index| core::str::from_utf8(index).ok()?.parse().ok())
+                .collect();
+            // Only a bare OSC 104 means "everything"; a list of junk means nothing
+            if requested.is_empty() || !indices.is_empty() {
+                self.events.push(TerminalEvent::ResetPaletteColors(indices));
+                return;
+            }
+        }
+
+        if let [b"1337", rest @ ..] = params
+            && let Some(event) = inline_image(rest)
+        {
+            self.events.push(event);
+            return;
+        }
+
+        let params: Vec<Vec<u8>> = params.iter().map(|p| p.to_vec()).collect();
+        self.events.push(TerminalEvent::Osc {
+            params,
+            bell_terminated,
+        });
+    }
+
+    // hook, put, unhook Device Control String
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        self.dcs = Some(PendingDcs {
+            params: params.iter().flat_map(|p| p.to_vec()).collect(),
+            intermediates: intermediates.to_vec(),
+            action,
+            data: Vec::new(),
+            overflowed: false,
+        });
+    }
+
+    fn put(&mut self, byte: u8) {
+        let limit = self.max_payload;
+        if let Some(dcs) = self.dcs.as_mut() {
+            if dcs.data.len() < limit {
+                dcs.data.push(byte);
+            } else {
+                dcs.overflowed = true;
+            }
+        }
+    }
+
+    fn unhook(&mut self) {
+        let Some(dcs) = self.dcs.take() else {
+            return;
+        };
+        let event = if dcs.overflowed {
+            TerminalEvent::OversizedPayload {
+                kind: PayloadKind::Dcs,
+                limit: self.max_payload,
+            }
+        } else if dcs.intermediates == b"$" && dcs.action == 'q' {
+            // DECRQSS - DCS $ q Pt ST
+            TerminalEvent::RequestStatus {
+                setting: dcs.data,
+                attrs: self.current_attrs.clone(),
+            }
+        } else if dcs.intermediates == b"+" && dcs.action == 'q' {
+            // XTGETTCAP - DCS + q Pt ST
+            TerminalEvent::RequestTermcap(dcs.data)
+        } else {
+            TerminalEvent::Dcs {
+                params: dcs.params,
+                intermediates: dcs.intermediates,
+                action: dcs.action,
+                data: dcs.data,
+            }
+        };
+        self.events.push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn parse(bytes: &[u8]) -> Parser {
+        let mut parser = Parser::new();
+        parser.advance(&mut vte::Parser::new(), bytes);
+        parser
+    }
+
+    fn is_unhandled(event: &TerminalEvent) -> bool {
+        matches!(
+            event,
+            TerminalEvent::UnhandledCsi { .. }
+                | TerminalEvent::UnhandledEsc(_)
+                | TerminalEvent::UnhandledSgr(_)
+        )
+    }
+
+    #[test]
+    fn unhandled_hook_fires_only_for_unhandled_events() {
+        let fired = Arc::new(AtomicUsize::new(0));
+        let all_unhandled = Arc::new(AtomicUsize::new(1));
+        let mut parser = Parser::new();
+        let (count, flag) = (fired.clone(), all_unhandled.clone());
+        parser.set_on_unhandled(move |event| {
+            count.fetch_add(1, Ordering::SeqCst);
+            if !is_unhandled(event) {
+                flag.store(0, Ordering::SeqCst);
+            }
+        });
+        parser.advance(&mut vte::Parser::new(), b"ab\x1b[1;5m\x1b[2Z\x1bc\x1b[2J");
+
+        let expected = parser.events.iter().filter(|e| is_unhandled(e)).count();
+        assert_eq!(expected, 3);
+        assert_eq!(fired.load(Ordering::SeqCst), expected);
+        assert_eq!(all_unhandled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn parser_without_hook_still_records_unhandled_events() {
+        let parser = parse(b"\x1b[2Z");
+        assert!(matches!(
+            parser.events.as_slice(),
+            [TerminalEvent::UnhandledCsi { action: 'Z', .. }]
+        ));
+    }
+
+    fn is_oversized(event: &TerminalEvent) -> bool {
+        matches!(
+            event,
+            TerminalEvent::OversizedPayload {
+                kind: PayloadKind::Osc,
+                limit: 8
+            }
+        )
+    }
+
+    fn printed(events: &[TerminalEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                TerminalEvent::Print { char, .. } => Some(*char),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oversized_osc_is_dropped_for_every_terminator() {
+        for input in [
+            &b"\x1b]0;abcdefghijklmnop\x07X"[..],
+            b"\x1b]0;abcdefghijklmnop\x1b\\X",
+        ] {
+            let mut parser = Parser::new();
+            parser.set_max_payload(8);
+            parser.advance(&mut vte::Parser::new(), input);
+            assert_eq!(parser.events.len(), 2, "{input:?}");
+            assert!(is_oversized(&parser.events[0]));
+            assert_eq!(printed(&parser.events), "X");
+        }
+    }
+
+    #[test]
+    fn osc_within_limit_is_not_cut_off() {
+        let mut parser = Parser::new();
+        parser.set_max_payload(8);
+        parser.advance(&mut vte::Parser::new(), b"\x1b]0;abc\x07X");
+        assert!(matches!(&parser.events[0], TerminalEvent::SetTitle(title) if title == "abc"));
+        assert!(!parser.events.iter().any(is_oversized));
+    }
+
+    #[test]
+    fn oversized_osc_split_across_feeds() {
+        let mut parser = Parser::new();
+        parser.set_max_payload(8);
+        let mut vte = vte::Parser::new();
+        for chunk in [&b"\x1b]0;abcdefg"[..], b"hijk", b"lmn\x1b", b"\\Y"] {
+            parser.advance(&mut vte, chunk);
+        }
+        assert_eq!(parser.events.len(), 2);
+        assert!(is_oversized(&parser.events[0]));
+        assert_eq!(printed(&parser.events), "Y");
+    }
+
+    #[test]
+    fn escape_other_than_st_ends_dropped_osc() {
+        let mut parser = Parser::new();
+        parser.set_max_payload(8);
+        parser.advance(&mut vte::Parser::new(), b"\x1b]0;abcdefghijklmnop\x1b[1AX");
+        assert!(is_oversized(&parser.events[0]));
+        assert!(matches!(parser.events[1], TerminalEvent::CursorUp(1)));
+        assert_eq!(printed(&parser.events), "X");
+    }
+
+    fn sgr(parser: &mut Parser, bytes: &[u8]) -> Attributes {
+        parser.advance(&mut vte::Parser::new(), bytes);
+        parser.current_attrs.clone()
+    }
+
+    #[test]
+    fn bright_background_colors() {
+        let mut parser = Parser::new();
+        assert_eq!(sgr(&mut parser, b"\x1b[101m").background, Color::BrightRed);
+        assert_eq!(
+            sgr(&mut parser, b"\x1b[107m").background,
+            Color::BrightWhite
+        );
+        assert_eq!(sgr(&mut parser, b"\x1b[0m").background, Color::Default);
+    }
+
+    #[test]
+    fn bare_extended_color_introducers_are_ignored() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            sgr(&mut parser, b"\x1b[48;5;33m").background,
+            Color::Indexed(33)
+        );
+        assert_eq!(sgr(&mut parser, b"\x1b[48m").background, Color::Indexed(33));
+        assert_eq!(sgr(&mut parser, b"\x1b[49m").background, Color::Default);
+        assert_eq!(
+            sgr(&mut parser, b"\x1b[48;2;1;2;3m").background,
+            Color::Rgb(1, 2, 3)
+        );
+        assert_eq!(sgr(&mut parser, b"\x1b[31;38m").foreground, Color::Red);
+        assert_eq!(sgr(&mut parser, b"\x1b[39;1m").foreground, Color::Default);
+        assert!(parser.events.is_empty(), "{:?}", parser.events);
+    }
+
+    #[test]
+    fn pen_is_default_only_after_full_reset() {
+        let mut parser = Parser::new();
+        assert!(sgr(&mut parser, b"\x1b[1;31m\x1b[0m").is_default());
+        assert!(!sgr(&mut parser, b"\x1b[3m").is_default());
+        assert!(!sgr(&mut parser, b"\x1b[23;44m").is_default());
+        assert!(sgr(&mut parser, b"\x1b[49m").is_default());
+        assert!(!sgr(&mut parser, b"\x1b[39;4m").is_default());
+    }
+
+    #[test]
+    fn stats_count_each_kind_of_input() {
+        let input = b"ab\r\n\x1b[31mc\x1b]0;t\x07\x07";
+        let parser = parse(input);
+        assert_eq!(
+            parser.stats(),
+            ParserStats {
+                bytes: input.len(),
+                prints: 3,
+                executes: 3,
+                csi_dispatches: 1,
+                osc_dispatches: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn attribute_builders_chain() {
+        let built = Attributes::default()
+            .with_fg(Color::Red)
+            .with_bg(Color::Indexed(4))
+            .with_bold(true)
+            .with_underline(true)
+            .with_bold(false);
+        let parser = parse(b"\x1b[31;48;5;4;4mx");
+        match &parser.events[0] {
+            TerminalEvent::Print { attrs, .. } => assert_eq!(attrs, &built),
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[test]
+    fn osc_events_keep_their_terminator() {
+        let parser = parse(b"\x1b]777;a\x07\x1b]777;b\x1b\\");
+        let terminators: Vec<bool> = parser
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                TerminalEvent::Osc {
+                    bell_terminated, ..
+                } => Some(*bell_terminated),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(terminators, [true, false]);
+    }
+
+    #[test]
+    fn split_utf8_waits_for_its_last_byte() {
+        let mut parser = Parser::new();
+        let mut vte = vte::Parser::new();
+        parser.advance(&mut vte, b"\xc3");
+        assert!(parser.events.is_empty());
+        parser.advance(&mut vte, b"\xa9");
+        assert_eq!(printed(&parser.events), "é");
+    }
+
+    #[test]
+    fn sgr_transition_emits_minimal_changes() {
+        let bold_red = Attributes::default().with_bold(true).with_fg(Color::Red);
+        let red = bold_red.clone().with_bold(false);
+        assert_eq!(red.sgr_transition(&bold_red), [22]);
+        assert_eq!(Attributes::default().sgr_transition(&bold_red), [0]);
+        assert!(bold_red.sgr_transition(&bold_red).is_empty());
+        assert_eq!(bold_red.sgr_transition(&Attributes::default()), [1, 31]);
+        let bold = Attributes::default().with_bold(true);
+        assert_eq!(bold.sgr_transition(&bold_red), [39]);
+    }
+
+    fn printed_foreground(bytes: &[u8]) -> Color {
+        match parse(bytes).events.pop() {
+            Some(TerminalEvent::Print { attrs, .. }) => attrs.foreground,
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[test]
+    fn out_of_range_color_components_clamp() {
+        assert_eq!(printed_foreground(b"\x1b[38;5;300mx"), Color::Indexed(255));
+        assert_eq!(printed_foreground(b"\x1b[38:5:300mx"), Color::Indexed(255));
+        assert_eq!(
+            printed_foreground(b"\x1b[38;2;1;999;3mx"),
+            Color::Rgb(1, 255, 3)
+        );
+        assert_eq!(printed_foreground(b"\x1b[38;5;44mx"), Color::Indexed(44));
+    }
+
+    #[test]
+    fn osc_1337_file_becomes_an_inline_image() {
+        let parser = parse(
+            b"\x1b]1337;File=name=aGk=;width=10;height=5px;inline=1:SGVsbG8sIHdvcmxkIQ==\x07",
+        );
+        match &parser.events[0] {
+            TerminalEvent::InlineImage { args, data } => {
+                assert_eq!(data, b"Hello, world!");
+                assert_eq!(args["width"], "10");
+                assert_eq!(args["height"], "5px");
+                assert_eq!(args["name"], "aGk=");
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[test]
+    fn inline_images_have_their_own_payload_limit() {
+        let image = b"\x1b]1337;File=inline=1:SGVsbG8sIHdvcmxkIQ==\x07X";
+        let mut parser = Parser::new();
+        parser.set_max_payload(8);
+        parser.advance(&mut vte::Parser::new(), image);
+        assert!(matches!(
+            &parser.events[0],
+            TerminalEvent::InlineImage { data, .. } if data == b"Hello, world!"
+        ));
+
+        parser.events.clear();
+        parser.set_max_image_payload(16);
+        parser.advance(&mut vte::Parser::new(), image);
+        assert!(matches!(
+            parser.events[0],
+            TerminalEvent::OversizedPayload {
+                kind: PayloadKind::InlineImage,
+                limit: 16
+            }
+        ));
+        assert_eq!(printed(&parser.events), "X");
+
+        // Other OSC strings keep the generic limit
+        parser.events.clear();
+        parser.advance(&mut vte::Parser::new(), b"\x1b]1337;Filex=abcdefgh\x07");
+        assert!(matches!(
+            parser.events[0],
+            TerminalEvent::OversizedPayload {
+                kind: PayloadKind::Osc,
+                limit: 8
+            }
+        ));
+    }
+
+    #[test]
+    fn malformed_osc_1337_stays_a_plain_osc() {
+        let parser = parse(b"\x1b]1337;Other\x07\x1b]1337;File=:@@\x07");
+        assert!(matches!(parser.events[0], TerminalEvent::Osc { .. }));
+        assert!(matches!(parser.events[1], TerminalEvent::Osc { .. }));
+        assert_eq!(decode_base64(b"YQ"), Some(b"a".to_vec()));
+        assert_eq!(decode_base64(b"Y Q=\n="), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn from_sgr_applies_params_onto_a_base() {
+        let base = Attributes::default().with_bold(true);
+        let attrs = Attributes::from_sgr(&[&[31], &[4]], base.clone());
+        assert!(attrs.bold && attrs.underline);
+        assert_eq!(attrs.foreground, Color::Red);
+
+        assert_eq!(
+            Attributes::from_sgr(&[], base.clone()),
+            Attributes::default()
+        );
+        assert_eq!(Attributes::from_sgr(&[&[0], &[1]], base.clone()), base);
+
+        let semicolons: &[&[u16]] = &[&[38], &[5], &[200], &[48], &[2], &[1], &[2], &[3]];
+        let attrs = Attributes::from_sgr(semicolons, Attributes::default());
+        assert_eq!(attrs.foreground, Color::Indexed(200));
+        assert_eq!(attrs.background, Color::Rgb(1, 2, 3));
+
+        let colons: &[&[u16]] = &[&[38, 5, 200], &[48, 2, 1, 2, 3]];
+        assert_eq!(Attributes::from_sgr(colons, Attributes::default()), attrs);
+    }
+
+    #[test]
+    fn from_sgr_accepts_the_colon_color_space_id() {
+        let base = Attributes::default();
+        let attrs = Attributes::from_sgr(&[&[38, 2, 0, 1, 2, 3]], base.clone());
+        assert_eq!(attrs.foreground, Color::Rgb(1, 2, 3));
+        let attrs = Attributes::from_sgr(&[&[48, 2, 0, 4, 5, 6]], base.clone());
+        assert_eq!(attrs.background, Color::Rgb(4, 5, 6));
+        let attrs = Attributes::from_sgr(&[&[58, 2, 0, 7, 8, 9]], base);
+        assert_eq!(attrs.underline_color, Color::Rgb(7, 8, 9));
+
+        let mut parser = Parser::new();
+        let attrs = sgr(&mut parser, b"\x1b[38:2::1:2:3;48:2::4:5:6m");
+        assert_eq!(attrs.foreground, Color::Rgb(1, 2, 3));
+        assert_eq!(attrs.background, Color::Rgb(4, 5, 6));
+    }
+
+    #[test]
+    fn from_sgr_drops_truncated_extended_colors() {
+        let base = Attributes::default().with_bold(true);
+        for params in [
+            &[&[38][..], &[5]][..],
+            &[&[38], &[2], &[255]],
+            &[&[48]],
+            &[&[38, 5]],
+        ] {
+            assert_eq!(Attributes::from_sgr(params, base.clone()), base);
+        }
+    }
+
+    #[test]
+    fn truncated_extended_colors_leave_the_pen_alone() {
+        for seq in [
+            "\x1b[38;5m",
+            "\x1b[38;2;255m",
+            "\x1b[38;2;255;1m",
+            "\x1b[48;5m",
+            "\x1b[48;2m",
+            "\x1b[48;2;255m",
+            "\x1b[38:5m",
+            "\x1b[48:2:1m",
+        ] {
+            let mut parser = Parser::new();
+            sgr(&mut parser, b"\x1b[32;41m");
+            let attrs = sgr(&mut parser, seq.as_bytes());
+            assert_eq!(attrs.foreground, Color::Green, "{seq:?}");
+            assert_eq!(attrs.background, Color::Red, "{seq:?}");
+        }
+    }
+
+    #[test]
+    fn sgr_resets_underline_style_and_color_separately() {
+        let base = Attributes::default();
+        let underlined = Attributes::from_sgr(&[&[4], &[58], &[5], &[9]], base.clone());
+        assert!(underlined.underline);
+        assert_eq!(underlined.underline_style, UnderlineStyle::Single);
+        assert_eq!(underlined.underline_color, Color::Indexed(9));
+
+        assert_eq!(Attributes::from_sgr(&[&[0]], underlined.clone()), base);
+
+        let attrs = Attributes::from_sgr(&[&[24]], underlined.clone());
+        assert!(!attrs.underline);
+        assert_eq!(attrs.underline_style, UnderlineStyle::None);
+        assert_eq!(attrs.underline_color, Color::Indexed(9));
+
+        let attrs = Attributes::from_sgr(&[&[59]], underlined.clone());
+        assert!(attrs.underline);
+        assert_eq!(attrs.underline_style, UnderlineStyle::Single);
+        assert_eq!(attrs.underline_color, Color::Default);
+    }
+
+    #[test]
+    fn colon_underline_styles_and_colors() {
+        let mut parser = Parser::new();
+        let curly = sgr(&mut parser, b"\x1b[4:3;58:2::1:2:3m");
+        assert!(curly.underline);
+        assert_eq!(curly.underline_style, UnderlineStyle::Curly);
+        assert_eq!(curly.underline_color, Color::Rgb(1, 2, 3));
+
+        let attrs = sgr(&mut parser, b"\x1b[4:0m");
+        assert!(!attrs.underline);
+        assert_eq!(attrs.underline_color, Color::Rgb(1, 2, 3));
+        assert_eq!(
+            sgr(&mut parser, b"\x1b[21m").underline_style,
+            UnderlineStyle::Double
+        );
+
+        let double = sgr(&mut parser, b"\x1b[58:2:4:5:6m");
+        assert_eq!(double.underline_color, Color::Rgb(4, 5, 6));
+        assert_eq!(double.sgr_transition(&curly), vec![21, 58, 2, 4, 5, 6]);
+        let params = double.sgr_params();
+        let params: Vec<&[u16]> = params.iter().map(core::slice::from_ref).collect();
+        assert_eq!(Attributes::from_sgr(&params, Attributes::default()), double);
+    }
+
+    #[test]
+    fn xtmodkeys_is_its_own_event() {
+        let parser = parse(b"\x1b[1m\x1b[>4;2m");
+        assert!(matches!(
+            parser.events.as_slice(),
+            [TerminalEvent::ModifyKeys {
+                resource: 4,
+                value: Some(2)
+            }]
+        ));
+        assert!(parser.current_attrs.bold);
+    }
+}
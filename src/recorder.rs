@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::terminal::{Terminal, escape_json};
+
+// Writes an asciinema v2 recording: a JSON header line followed by one
+// `[time, "o", data]` line per chunk of terminal output
+pub struct Recorder<W: Write> {
+    writer: W,
+    // Trailing bytes of a UTF-8 sequence split across chunks
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W, cols: usize, rows: usize) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "{{\"version\": 2, \"width\": {cols}, \"height\": {rows}}}"
+        )?;
+        Ok(Recorder {
+            writer,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn record(&mut self, when: Duration, data: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        let text = take_utf8(&mut self.pending);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(
+            self.writer,
+            "[{:.6}, \"o\", \"{}\"]",
+            when.as_secs_f64(),
+            escape_json(&text)
+        )
+    }
+
+    // Record a chunk and apply it to the terminal in one step
+    pub fn feed(&mut self, terminal: &mut Terminal, when: Duration, data: &[u8]) -> io::Result<()> {
+        terminal.feed(data);
+        self.record(when, data)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Drain everything except an incomplete UTF-8 sequence at the very end
+fn take_utf8(bytes: &mut Vec<u8>) -> String {
+    let keep = match std::str::from_utf8(bytes) {
+        Ok(_) => 0,
+        Err(err) if err.error_len().is_none() => bytes.len() - err.valid_up_to(),
+        Err(_) => 0,
+    };
+    let rest = bytes.split_off(bytes.len() - keep);
+    let text = String::from_utf8_lossy(bytes).into_owned();
+    *bytes = rest;
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_and_output_events() {
+        let mut terminal = Terminal::new(80, 24);
+        let mut recorder = Recorder::new(Vec::new(), 80, 24).unwrap();
+        recorder
+            .feed(
+                &mut terminal,
+                Duration::from_millis(1500),
+                b"hi\x1b[1m\"\r\n",
+            )
+            .unwrap();
+
+        let out = String::from_utf8(recorder.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+             [1.500000, \"o\", \"hi\\u001b[1m\\\"\\r\\n\"]\n"
+        );
+        assert!(terminal.render_content().starts_with("hi\""));
+    }
+
+    #[test]
+    fn holds_back_split_utf8_until_complete() {
+        let mut recorder = Recorder::new(Vec::new(), 80, 24).unwrap();
+        recorder
+            .record(Duration::from_secs(1), b"\xe2\x82")
+            .unwrap();
+        recorder.record(Duration::from_secs(2), b"\xac").unwrap();
+
+        let out = String::from_utf8(recorder.into_inner()).unwrap();
+        assert_eq!(out.lines().nth(1), Some("[2.000000, \"o\", \"€\"]"));
+        assert_eq!(out.lines().count(), 2);
+    }
+}
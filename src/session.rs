@@ -0,0 +1,141 @@
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use anyhow::Result;
+use portable_pty::{Child, CommandBuilder, MasterPty, PtySize, native_pty_system};
+
+use crate::terminal::Terminal;
+
+// A child process on a PTY wired to a Terminal. PTY reads block, so a
+// background thread forwards output over a channel and `poll` drains it
+// without waiting, which suits driving the session from an event loop.
+// Under tokio, `AsyncSession` reads the PTY through the reactor instead.
+pub struct Session {
+    terminal: Terminal,
+    // Kept open for the child's lifetime; dropping it hangs up the PTY
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    output: Receiver<Vec<u8>>,
+}
+
+impl Session {
+    pub fn spawn(command: CommandBuilder, cols: usize, rows: usize) -> Result<Self> {
+        let terminal = Terminal::new(cols, rows);
+        let (cols, rows) = terminal.size();
+        let pair = native_pty_system().openpty(PtySize {
+            rows: rows.min(u16::MAX as usize) as u16,
+            cols: cols.min(u16::MAX as usize) as u16,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        let child = pair.slave.spawn_command(command)?;
+        let mut reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if sender.send(buffer[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Session {
+            terminal,
+            master: pair.master,
+            writer,
+            child,
+            output,
+        })
+    }
+
+    /// Feeds whatever output the child has produced so far, without blocking.
+    /// Returns the terminal's replies to write back to the child (possibly
+    /// empty), or None when no output was pending.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        let mut fed = false;
+        while let Ok(bytes) = self.output.try_recv() {
+            self.terminal.feed(&bytes);
+            fed = true;
+        }
+        fed.then(|| self.terminal.take_responses())
+    }
+
+    /// Resizes the terminal and tells the child's PTY the new size.
+    pub fn resize(&mut self, cols: usize, rows: usize) -> Result<()> {
+        self.terminal.resize(cols, rows);
+        let (cols, rows) = self.terminal.size();
+        self.master.resize(PtySize {
+            rows: rows.min(u16::MAX as usize) as u16,
+            cols: cols.min(u16::MAX as usize) as u16,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.writer.flush()
+    }
+
+    pub fn terminal(&self) -> &Terminal {
+        &self.terminal
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut Terminal {
+        &mut self.terminal
+    }
+
+    pub fn wait(&mut self) -> io::Result<portable_pty::ExitStatus> {
+        self.child.wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn shell(script: &str) -> Option<CommandBuilder> {
+        if !Path::new("/bin/sh").exists() {
+            return None;
+        }
+        let mut command = CommandBuilder::new("/bin/sh");
+        command.args(["-c", script]);
+        Some(command)
+    }
+
+    #[test]
+    fn poll_feeds_output_and_returns_responses() {
+        let Some(command) = shell("printf 'hi\\033[6n'; sleep 0.3") else {
+            return;
+        };
+        let mut session = Session::spawn(command, 20, 3).unwrap();
+        let mut responses = None;
+        for _ in 0..200 {
+            if let Some(reply) = session.poll().filter(|reply| !reply.is_empty()) {
+                responses = Some(reply);
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(responses.unwrap(), b"\x1b[1;3R");
+        assert!(session.terminal().render_content().starts_with("hi"));
+        assert_eq!(session.poll(), None);
+
+        session.resize(30, 4).unwrap();
+        assert_eq!(session.terminal().size(), (30, 4));
+        session.wait().unwrap();
+    }
+}
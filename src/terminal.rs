@@ -0,0 +1,4069 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hasher;
+use core::ops::Range;
+use core::time::Duration;
+
+use crate::parser::{Attributes, Color, LineAttribute, Parser, Rect, TerminalEvent};
+
+// Session 3 - Terminal State Types
+
+type Rgb = (u8, u8, u8);
+
+// Modes with a meaningful power-on value, restored by DECSTR:
+// IRM off, DECOM off, DECAWM on, DECTCEM (cursor visible) on, DECLRMM off
+const DEFAULT_MODES: [((bool, u16), bool); 6] = [
+    ((false, 4), false),
+    ((true, 1), false),
+    ((true, 6), false),
+    ((true, 7), true),
+    ((true, 25), true),
+    ((true, 69), false),
+];
+
+// Pixel size of one cell reported by XTWINOPS 14t until the host sets its own
+const DEFAULT_CELL_PIXEL_SIZE: (usize, usize) = (8, 16);
+
+// Colors used for Color::Default when resolving to RGB
+const DEFAULT_FOREGROUND: Rgb = (229, 229, 229);
+const DEFAULT_BACKGROUND: Rgb = (0, 0, 0);
+
+// xterm's stock 256-color palette: 16 ANSI colors, a 6x6x6 cube, then 24 grays
+fn default_palette() -> [Rgb; 256] {
+    const ANSI: [Rgb; 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let level = |n: usize| if n == 0 { 0 } else { (55 + n * 40) as u8 };
+
+    let mut palette = [(0, 0, 0); 256];
+    palette[..16].copy_from_slice(&ANSI);
+    for i in 0..216 {
+        palette[16 + i] = (level(i / 36), level(i / 6 % 6), level(i % 6));
+    }
+    for i in 0..24 {
+        let gray = (8 + i * 10) as u8;
+        palette[232 + i] = (gray, gray, gray);
+    }
+    palette
+}
+
+fn resolve_color(palette: &[Rgb; 256], color: Color, default: Rgb) -> Rgb {
+    match color {
+        Color::Default => default,
+        Color::Black => palette[0],
+        Color::Red => palette[1],
+        Color::Green => palette[2],
+        Color::Yellow => palette[3],
+        Color::Blue => palette[4],
+        Color::Magenta => palette[5],
+        Color::Cyan => palette[6],
+        Color::White => palette[7],
+        Color::BrightBlack => palette[8],
+        Color::BrightRed => palette[9],
+        Color::BrightGreen => palette[10],
+        Color::BrightYellow => palette[11],
+        Color::BrightBlue => palette[12],
+        Color::BrightMagenta => palette[13],
+        Color::BrightCyan => palette[14],
+        Color::BrightWhite => palette[15],
+        Color::Indexed(n) => palette[n as usize],
+        Color::Rgb(r, g, b) => (r, g, b),
+    }
+}
+
+// Pen produced by an SGR parameter string such as "1;31" or "4:3"; empty
+// params count as 0
+fn attrs_from_sgr(params: &str) -> Option<Attributes> {
+    let params = params
+        .split(';')
+        .map(|param| {
+            param
+                .split(':')
+                .map(|p| {
+                    if p.is_empty() {
+                        Some(0)
+                    } else {
+                        p.parse().ok()
+                    }
+                })
+                .collect::<Option<Vec<u16>>>()
+        })
+        .collect::<Option<Vec<Vec<u16>>>>()?;
+    let params: Vec<&[u16]> = params.iter().map(Vec::as_slice).collect();
+    Some(Attributes::from_sgr(&params, Attributes::default()))
+}
+
+// JSON string body escaping, shared with the asciicast recorder
+pub(crate) fn escape_json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Flat, FFI-friendly view of a cell with colors already resolved
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellExport {
+    pub character: u32,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+    pub flags: u8,
+}
+
+impl CellExport {
+    pub const BOLD: u8 = 1 << 0;
+    pub const ITALIC: u8 = 1 << 1;
+    pub const UNDERLINE: u8 = 1 << 2;
+    pub const INVERSE: u8 = 1 << 3;
+    pub const CONTINUATION: u8 = 1 << 4;
+}
+
+// Display columns a character occupies: 0 for combining marks and other
+// zero-width characters, 2 for East Asian wide/fullwidth and emoji, else 1
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F
+        | 0x0483..=0x0489
+        | 0x0591..=0x05BD
+        | 0x0610..=0x061A
+        | 0x064B..=0x065F
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x200B..=0x200F
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        // Emoji-presentation symbols; the rest of these blocks stay narrow
+        | 0x231A..=0x231B
+        | 0x23E9..=0x23EC
+        | 0x23F0
+        | 0x23F3
+        | 0x25FD..=0x25FE
+        | 0x2614..=0x2615
+        | 0x2648..=0x2653
+        | 0x267F
+        | 0x2693
+        | 0x26A1
+        | 0x26AA..=0x26AB
+        | 0x26BD..=0x26BE
+        | 0x26C4..=0x26C5
+        | 0x26CE
+        | 0x26D4
+        | 0x26EA
+        | 0x26F2..=0x26F3
+        | 0x26F5
+        | 0x26FA
+        | 0x26FD
+        | 0x2705
+        | 0x270A..=0x270B
+        | 0x2728
+        | 0x274C
+        | 0x274E
+        | 0x2753..=0x2755
+        | 0x2757
+        | 0x2795..=0x2797
+        | 0x27B0
+        | 0x27BF
+        | 0x2B1B..=0x2B1C
+        | 0x2B50
+        | 0x2B55
+        | 0x1F300..=0x1F64F
+        | 0x1F680..=0x1F6FF
+        | 0x1F900..=0x1F9FF
+        | 0x1FA70..=0x1FAFF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+// Columns `s` takes up when printed, using the same widths as `Terminal::print`
+pub fn str_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+// Terminfo capabilities answered over XTGETTCAP
+fn termcap_value(name: &[u8]) -> Option<&'static str> {
+    match name {
+        b"colors" | b"Co" => Some("256"),
+        b"TN" | b"name" => Some("xterm-256color"),
+        _ => None,
+    }
+}
+
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+// One-shot helper for captured output: the plain text a fresh terminal of
+// this size shows after `input` (as `Terminal::render_content`)
+pub fn render(cols: usize, rows: usize, input: &[u8]) -> String {
+    let mut terminal = Terminal::new(cols, rows);
+    terminal.feed(input);
+    terminal.render_content()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    character: char,
+    attrs: Attributes,
+    // Right half of a wide character; the glyph lives in the cell to the left
+    continuation: bool,
+    // Index into the terminal's OSC 8 link table
+    link: Option<u32>,
+    // Zero-width marks printed after `character`, drawn over the same cell
+    combining: String,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            character: ' ',
+            attrs: Attributes::default(),
+            continuation: false,
+            link: None,
+            combining: String::new(),
+        }
+    }
+}
+
+impl Cell {
+    pub fn character(&self) -> char {
+        self.character
+    }
+
+    pub fn combining(&self) -> &str {
+        &self.combining
+    }
+
+    // The base character followed by any combining marks
+    fn push_text(&self, out: &mut String) {
+        out.push(self.character);
+        out.push_str(&self.combining);
+    }
+
+    pub fn attrs(&self) -> &Attributes {
+        &self.attrs
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.continuation
+    }
+}
+
+// Output backend driven cell by cell over the visible grid; continuation
+// cells are passed through so backends can decide how to treat them
+pub trait Renderer {
+    type Output;
+
+    fn begin_line(&mut self, _row: usize) {}
+    fn cell(&mut self, cell: &Cell);
+    fn end_line(&mut self, _row: usize) {}
+    fn finish(self) -> Self::Output;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+// Plain-text layout choices; the default matches `render_content`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    pub trim_trailing: bool,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            trim_trailing: false,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+        }
+    }
+}
+
+// Characters only, one line per row
+#[derive(Default)]
+pub struct PlainRenderer {
+    output: String,
+    options: RenderOptions,
+}
+
+impl Renderer for PlainRenderer {
+    type Output = String;
+
+    fn cell(&mut self, cell: &Cell) {
+        if !cell.continuation {
+            cell.push_text(&mut self.output);
+        }
+    }
+
+    fn end_line(&mut self, _row: usize) {
+        if self.options.trim_trailing {
+            self.output
+                .truncate(self.output.trim_end_matches(' ').len());
+        }
+        self.output.push_str(self.options.line_ending.as_str());
+    }
+
+    fn finish(mut self) -> String {
+        if !self.options.final_newline {
+            let ending = self.options.line_ending.as_str();
+            if self.output.ends_with(ending) {
+                self.output.truncate(self.output.len() - ending.len());
+            }
+        }
+        self.output
+    }
+}
+
+// SGR-styled text; each styled line ends with a reset so lines stand alone
+#[derive(Default)]
+pub struct AnsiRenderer {
+    output: String,
+    pen: Attributes,
+    // Cell drawn with inverted attributes, as (row, col)
+    cursor: Option<(usize, usize)>,
+    position: (usize, usize),
+    // When set, every color (including the defaults) goes out as 24-bit RGB
+    palette: Option<[Rgb; 256]>,
+}
+
+impl Renderer for AnsiRenderer {
+    type Output = String;
+
+    fn begin_line(&mut self, row: usize) {
+        self.position = (row, 0);
+    }
+
+    fn cell(&mut self, cell: &Cell) {
+        let at_cursor = self.cursor == Some(self.position);
+        self.position.1 += 1;
+        if cell.continuation {
+            return;
+        }
+        let mut attrs = if at_cursor {
+            cell.attrs.clone().with_inverse(!cell.attrs.inverse)
+        } else {
+            cell.attrs.clone()
+        };
+        if let Some(palette) = &self.palette {
+            let rgb = |color, default| {
+                let (r, g, b) = resolve_color(palette, color, default);
+                Color::Rgb(r, g, b)
+            };
+            attrs.foreground = rgb(attrs.foreground, DEFAULT_FOREGROUND);
+            attrs.background = rgb(attrs.background, DEFAULT_BACKGROUND);
+            // A default underline color follows the text, so it stays unset
+            if attrs.underline_color != Color::Default {
+                attrs.underline_color = rgb(attrs.underline_color, DEFAULT_FOREGROUND);
+            }
+        }
+        if attrs != self.pen {
+            // Empty when the difference has no semicolon SGR form (e.g. curly
+            // to single underline); an empty `ESC [ m` would reset instead
+            let params = attrs.sgr_transition(&self.pen);
+            if !params.is_empty() {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                self.output.push_str(&format!("\x1b[{}m", params.join(";")));
+            }
+            self.pen = attrs;
+        }
+        cell.push_text(&mut self.output);
+    }
+
+    fn end_line(&mut self, _row: usize) {
+        if !self.pen.is_default() {
+            self.output.push_str("\x1b[0m");
+            self.pen = Attributes::default();
+        }
+        self.output.push('\n');
+    }
+
+    fn finish(self) -> String {
+        self.output
+    }
+}
+
+// A <pre> block with inline-styled spans, colors resolved through the palette
+pub struct HtmlRenderer {
+    output: String,
+    palette: [Rgb; 256],
+    span: Option<Attributes>,
+}
+
+impl HtmlRenderer {
+    pub fn new(palette: [Rgb; 256]) -> Self {
+        HtmlRenderer {
+            output: String::from("<pre>"),
+            palette,
+            span: None,
+        }
+    }
+
+    fn close_span(&mut self) {
+        if self.span.take().is_some() {
+            self.output.push_str("</span>");
+        }
+    }
+
+    fn open_span(&mut self, attrs: &Attributes) {
+        let mut fg = resolve_color(&self.palette, attrs.foreground, DEFAULT_FOREGROUND);
+        let mut bg = resolve_color(&self.palette, attrs.background, DEFAULT_BACKGROUND);
+        if attrs.inverse {
+            core::mem::swap(&mut fg, &mut bg);
+        }
+
+        let mut style = format!(
+            "color:#{:02x}{:02x}{:02x};background-color:#{:02x}{:02x}{:02x}",
+            fg.0, fg.1, fg.2, bg.0, bg.1, bg.2
+        );
+        if attrs.bold {
+            style.push_str(";font-weight:bold");
+        }
+        if attrs.italic {
+            style.push_str(";font-style:italic");
+        }
+        if attrs.underline {
+            style.push_str(";text-decoration:underline");
+        }
+        self.output.push_str(&format!("<span style=\"{style}\">"));
+        self.span = Some(attrs.clone());
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    type Output = String;
+
+    fn cell(&mut self, cell: &Cell) {
+        if cell.continuation {
+            return;
+        }
+        // Default-styled text sits outside any span
+        let wanted = (!cell.attrs.is_default()).then_some(&cell.attrs);
+        if self.span.as_ref() != wanted {
+            self.close_span();
+            if let Some(attrs) = wanted {
+                self.open_span(attrs);
+            }
+        }
+        match cell.character {
+            '&' => self.output.push_str("&amp;"),
+            '<' => self.output.push_str("&lt;"),
+            '>' => self.output.push_str("&gt;"),
+            c => self.output.push(c),
+        }
+        self.output.push_str(&cell.combining);
+    }
+
+    fn end_line(&mut self, _row: usize) {
+        self.close_span();
+        self.output.push('\n');
+    }
+
+    fn finish(mut self) -> String {
+        self.output.push_str("</pre>");
+        self.output
+    }
+}
+
+// FNV-1a, so screen hashes stay stable across runs and builds without std
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // Integers go in little-endian whatever the host, keeping hashes portable
+    fn write_u16(&mut self, n: u16) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Arrow keys, for `Terminal::encode_arrow`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Right,
+    Left,
+}
+
+// Mouse reporting level; modes 1000, 1002 and 1003 are exclusive, so the
+// most recently enabled one wins
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    // 1000: button presses and releases
+    Click,
+    // 1002: also motion while a button is held
+    ButtonEvent,
+    // 1003: all motion
+    AnyEvent,
+}
+
+// Run of adjacent cells sharing the same attributes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub attrs: Attributes,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+}
+
+// Lines kept after scrolling off the top of the primary screen
+const DEFAULT_SCROLLBACK_LIMIT: usize = 10_000;
+
+// DA2 reply: VT220, firmware version 10, no ROM cartridge
+const DEFAULT_DA2_RESPONSE: &[u8] = b"\x1b[>1;10;0c";
+
+// Largest grid `resize` will allocate in either dimension unless the host changes it
+const DEFAULT_MAX_GRID_SIZE: usize = 10_000;
+
+// Link table size that triggers dropping links no cell refers to any more
+const LINK_GC_THRESHOLD: usize = 256;
+
+fn row_segments(row: &[Cell]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for cell in row.iter().filter(|cell| !cell.continuation) {
+        match segments.last_mut() {
+            Some(segment) if segment.attrs == cell.attrs => {
+                cell.push_text(&mut segment.text);
+            }
+            _ => {
+                let mut text = String::new();
+                cell.push_text(&mut text);
+                segments.push(Segment {
+                    text,
+                    attrs: cell.attrs.clone(),
+                });
+            }
+        }
+    }
+    segments
+}
+
+fn blank_row(cols: usize) -> Vec<Cell> {
+    (0..cols).map(|_| Cell::default()).collect()
+}
+
+// Power-on tab stops, every eight columns
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|col| col % 8 == 0 && col != 0).collect()
+}
+
+fn blank_grid(cols: usize, rows: usize) -> Vec<Vec<Cell>> {
+    (0..rows).map(|_| blank_row(cols)).collect()
+}
+
+type LineHook = Box<dyn FnMut(&str) + Send>;
+
+// (row, columns) runs of cells covered by one hyperlink, in reading order
+pub type LinkRuns = Vec<(usize, Range<usize>)>;
+
+pub struct Terminal {
+    grid: Vec<Vec<Cell>>,
+    // Whichever of the primary/alternate buffers is not currently displayed
+    inactive_grid: Vec<Vec<Cell>>,
+    alt_screen: bool,
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_limit: usize,
+    // Called with each line's text as it scrolls off the top of the primary screen
+    on_line_complete: Option<LineHook>,
+    line_attrs: Vec<LineAttribute>,
+    // Line attributes of the inactive buffer, swapped along with its cells
+    inactive_line_attrs: Vec<LineAttribute>,
+    // Rows whose text continues on the next row because of autowrap
+    wrapped: Vec<bool>,
+    inactive_wrapped: Vec<bool>,
+    // Whether each column holds a tab stop
+    tab_stops: Vec<bool>,
+    // Mode states keyed by (private, number), seeded with power-on defaults
+    modes: BTreeMap<(bool, u16), bool>,
+    // XTSAVE snapshots of private modes, one slot per mode number
+    saved_modes: BTreeMap<u16, bool>,
+    // OSC 22 pointer shape for the GUI host to apply
+    pointer_shape: Option<String>,
+    title: String,
+    // Distinct OSC 8 (id, URI) pairs, referenced by index from cells
+    links: Vec<(Option<String>, String)>,
+    // Reverse lookup into `links`
+    link_index: BTreeMap<(Option<String>, String), u32>,
+    // Table size at which the next unreferenced-link sweep runs
+    link_gc_at: usize,
+    // Link applied to newly printed cells
+    hyperlink: Option<u32>,
+    palette: [Rgb; 256],
+    // Bumped by every mutation so a host can skip unchanged frames
+    generation: u64,
+    // The grid as of the last `take_damage`, for diffing
+    damage_base: Vec<Vec<Cell>>,
+    cursor: Cursor,
+    // Set after a glyph lands in the last column; the wrap itself waits for
+    // the next glyph, and any cursor movement or edit cancels it
+    wrap_pending: bool,
+    saved_cursor: Cursor,
+    // DECKPAM/DECKPNM: whether the numeric keypad sends application sequences
+    application_keypad: bool,
+    // XTMODKEYS resource -> value, e.g. 4 for modifyOtherKeys
+    modify_keys: BTreeMap<u16, u16>,
+    mouse_tracking: MouseTracking,
+    // Last graphic character written, for REP
+    last_printed: Option<(char, Attributes)>,
+    // DECSCUSR code: 0/1 blinking block, 2 steady block, 3/4 underline, 5/6 bar
+    cursor_style: u16,
+    // DECSLRM columns, inclusive; only narrower than the screen while DECLRMM is on
+    left_margin: usize,
+    right_margin: usize,
+    // DECSTBM scroll region rows, inclusive
+    top_margin: usize,
+    bottom_margin: usize,
+    // Replies to queries such as DSR, waiting to be written back to the PTY
+    responses: Vec<u8>,
+    // Reply to ENQ; empty by default so nothing is sent
+    answerback: String,
+    // Sent verbatim for secondary device attributes
+    da2_response: Vec<u8>,
+    // Terminal unit ID reported by DA3 as DCS ! | id ST, conventionally 8 hex digits
+    unit_id: String,
+    // (width, height) of a cell in pixels, for size reports
+    cell_pixel_size: (usize, usize),
+    // Retained across feed calls so escape sequences and UTF-8 characters split
+    // between reads still parse
+    vte: vte::Parser,
+    parser: Parser,
+    rows: usize,
+    cols: usize,
+    // Upper bounds for `resize`, so a hostile XTWINOPS request cannot exhaust memory
+    max_cols: usize,
+    max_rows: usize,
+}
+
+impl Terminal {
+    // Dimensions below 1x1 are raised to it; row/column arithmetic assumes a cell exists
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        let mut parser = Parser::new();
+        parser.set_batch_prints(true);
+
+        Terminal {
+            grid: blank_grid(cols, rows),
+            inactive_grid: blank_grid(cols, rows),
+            alt_screen: false,
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            on_line_complete: None,
+            line_attrs: vec![LineAttribute::default(); rows],
+            inactive_line_attrs: vec![LineAttribute::default(); rows],
+            wrapped: vec![false; rows],
+            inactive_wrapped: vec![false; rows],
+            tab_stops: default_tab_stops(cols),
+            modes: DEFAULT_MODES.into_iter().collect(),
+            saved_modes: BTreeMap::new(),
+            pointer_shape: None,
+            title: String::new(),
+            links: Vec::new(),
+            link_index: BTreeMap::new(),
+            link_gc_at: LINK_GC_THRESHOLD,
+            hyperlink: None,
+            palette: default_palette(),
+            generation: 0,
+            damage_base: blank_grid(cols, rows),
+            cursor: Cursor::default(),
+            wrap_pending: false,
+            saved_cursor: Cursor::default(),
+            application_keypad: false,
+            modify_keys: BTreeMap::new(),
+            cursor_style: 0,
+            mouse_tracking: MouseTracking::Off,
+            last_printed: None,
+            left_margin: 0,
+            right_margin: cols - 1,
+            top_margin: 0,
+            bottom_margin: rows - 1,
+            responses: Vec::new(),
+            answerback: String::new(),
+            da2_response: DEFAULT_DA2_RESPONSE.to_vec(),
+            unit_id: String::from("00000000"),
+            cell_pixel_size: DEFAULT_CELL_PIXEL_SIZE,
+            vte: vte::Parser::new(),
+            parser,
+            rows,
+            cols,
+            max_cols: DEFAULT_MAX_GRID_SIZE,
+            max_rows: DEFAULT_MAX_GRID_SIZE,
+        }
+    }
+
+    pub fn set_on_line_complete(&mut self, hook: impl FnMut(&str) + Send + 'static) {
+        self.on_line_complete = Some(Box::new(hook));
+    }
+
+    pub fn set_max_size(&mut self, cols: usize, rows: usize) {
+        self.max_cols = cols.max(1);
+        self.max_rows = rows.max(1);
+    }
+
+    // Change the grid size, clamped to the configured maximum. Content keeps
+    // its position; margins reset to the full screen.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.clamp(1, self.max_cols);
+        let rows = rows.clamp(1, self.max_rows);
+        if (cols, rows) == (self.cols, self.rows) {
+            return;
+        }
+        self.bump_generation();
+
+        for grid in [
+            &mut self.grid,
+            &mut self.inactive_grid,
+            &mut self.damage_base,
+        ] {
+            grid.resize_with(rows, || blank_row(cols));
+            for row in grid.iter_mut() {
+                row.resize_with(cols, Cell::default);
+            }
+        }
+        self.line_attrs.resize(rows, LineAttribute::default());
+        self.inactive_line_attrs
+            .resize(rows, LineAttribute::default());
+        self.wrapped.resize(rows, false);
+        self.inactive_wrapped.resize(rows, false);
+        let stops = default_tab_stops(cols);
+        if cols > self.cols {
+            self.tab_stops.extend_from_slice(&stops[self.cols..]);
+        } else {
+            self.tab_stops.truncate(cols);
+        }
+
+        self.rows = rows;
+        self.cols = cols;
+        self.top_margin = 0;
+        self.bottom_margin = rows - 1;
+        self.left_margin = 0;
+        self.right_margin = cols - 1;
+        // Both the live and the saved cursor must land inside the new grid, or a
+        // later print or restore would index out of bounds
+        self.wrap_pending = false;
+        for cursor in [&mut self.cursor, &mut self.saved_cursor] {
+            cursor.row = cursor.row.min(rows - 1);
+            cursor.col = cursor.col.min(cols - 1);
+        }
+    }
+
+    // Sized from COLUMNS/LINES, falling back to 80x24 when unset or unparsable
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Terminal {
+        fn dimension(var: &str, fallback: usize) -> usize {
+            std::env::var(var)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(fallback)
+        }
+
+        Terminal::new(dimension("COLUMNS", 80), dimension("LINES", 24))
+    }
+
+    // Changed cells since the previous call, as one 1-based rectangle per row
+    // spanning the leftmost to the rightmost change
+    pub fn take_damage(&mut self) -> Vec<Rect> {
+        let mut damage = Vec::new();
+        for (row, (now, before)) in self.grid.iter().zip(&self.damage_base).enumerate() {
+            let changed = |col: &usize| now[*col] != before[*col];
+            let Some(left) = (0..self.cols).find(changed) else {
+                continue;
+            };
+            let right = (0..self.cols).rfind(changed).unwrap_or(left);
+            damage.push(Rect {
+                top: row as u16 + 1,
+                left: left as u16 + 1,
+                bottom: row as u16 + 1,
+                right: right as u16 + 1,
+            });
+        }
+        self.damage_base.clone_from(&self.grid);
+        damage
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn bump_generation(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    fn print(&mut self, c: char, attrs: Attributes) {
+        // NUL and DEL are never graphic, even if they slip past the parser
+        if c == '\0' || c == '\x7f' {
+            return;
+        }
+        // Combining marks have no cell of their own
+        let width = char_width(c);
+        if width == 0 {
+            self.combine(c);
+            return;
+        }
+
+        self.bump_generation();
+        if core::mem::take(&mut self.wrap_pending) && self.autowrap() {
+            let right_edge = self.right_edge();
+            self.wrap_line(right_edge);
+        }
+        let right_edge = self.right_edge();
+
+        // A wide glyph never straddles the edge; it moves to the next line whole
+        if width == 2 && self.cursor.col + 1 > right_edge && self.cursor.col > 0 {
+            if self.autowrap() {
+                self.wrap_line(right_edge);
+            } else {
+                self.cursor.col = right_edge - 1;
+            }
+        }
+
+        self.last_printed = Some((c, attrs.clone()));
+        // IRM: printing shifts existing cells right instead of overwriting
+        if self.mode_enabled(false, 4) {
+            self.insert_chars(width as u16);
+        }
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        self.break_wide_pair(row, col);
+        if width == 2 && col + 1 < self.cols {
+            self.break_wide_pair(row, col + 1);
+            self.grid[row][col + 1] = Cell {
+                character: ' ',
+                attrs: attrs.clone(),
+                continuation: true,
+                link: self.hyperlink,
+                combining: String::new(),
+            };
+        }
+        self.grid[row][col] = Cell {
+            character: c,
+            attrs,
+            continuation: false,
+            link: self.hyperlink,
+            combining: String::new(),
+        };
+
+        self.cursor.col += width;
+        if self.cursor.col > right_edge {
+            // The cursor stays on the last column until the next glyph; without
+            // DECAWM that glyph simply overwrites it
+            self.cursor.col = right_edge;
+            self.wrap_pending = self.autowrap();
+        }
+    }
+
+    // Text that starts inside the left/right margins wraps at the right margin
+    fn right_edge(&self) -> usize {
+        if self.cursor.col <= self.right_margin {
+            self.right_margin
+        } else {
+            self.cols - 1
+        }
+    }
+
+    // Attach a zero-width mark to the glyph just before the cursor, which is
+    // the cursor cell itself while a wrap is pending
+    fn combine(&mut self, c: char) {
+        let (row, mut col) = (self.cursor.row, self.cursor.col);
+        if !self.wrap_pending {
+            let Some(previous) = col.checked_sub(1) else {
+                return;
+            };
+            col = previous;
+        }
+        if self.grid[row][col].continuation && col > 0 {
+            col -= 1;
+        }
+        self.grid[row][col].combining.push(c);
+        self.bump_generation();
+    }
+
+    // Blanks both halves of a wide glyph when either is about to be overwritten
+    fn break_wide_pair(&mut self, row: usize, col: usize) {
+        let line = &mut self.grid[row];
+        if line[col].continuation {
+            if col > 0 {
+                line[col - 1] = Cell::default();
+            }
+            line[col] = Cell::default();
+        } else if col + 1 < line.len() && line[col + 1].continuation {
+            line[col + 1] = Cell::default();
+        }
+    }
+
+    fn wrap_line(&mut self, right_edge: usize) {
+        self.wrapped[self.cursor.row] = true;
+        self.line_feed();
+        if right_edge == self.right_margin {
+            self.cursor.col = self.left_margin;
+        }
+    }
+
+    // Write one cell without touching the cursor; false when out of bounds
+    pub fn put_cell(&mut self, row: usize, col: usize, c: char, attrs: Attributes) -> bool {
+        if row >= self.rows || col >= self.cols {
+            return false;
+        }
+        self.bump_generation();
+        self.write_cell(row, col, c, attrs);
+        true
+    }
+
+    // Overwrite one in-bounds cell, blanking any wide glyph it cuts in half
+    fn write_cell(&mut self, row: usize, col: usize, c: char, attrs: Attributes) {
+        self.break_wide_pair(row, col);
+        self.grid[row][col] = Cell {
+            character: c,
+            attrs,
+            continuation: false,
+            link: None,
+            combining: String::new(),
+        };
+    }
+
+    // Print text for tests and demos, wrapping at the right margin exactly as
+    // `print` does even when DECAWM is off; '\n' starts a new line
+    pub fn print_wrapped(&mut self, text: &str, attrs: Attributes) {
+        let autowrap = self.autowrap();
+        self.modes.insert((true, 7), true);
+        for c in text.chars() {
+            if c == '\n' {
+                self.carriage_return();
+                self.line_feed();
+            } else {
+                self.print(c, attrs.clone());
+            }
+        }
+        self.modes.insert((true, 7), autowrap);
+    }
+
+    // REP goes through `print`, so wide glyphs and wrapping behave as if typed
+    fn repeat(&mut self, n: u16) {
+        let Some((c, attrs)) = self.last_printed.clone() else {
+            return;
+        };
+        for _ in 0..n {
+            self.print(c, attrs.clone());
+        }
+    }
+
+    fn print_str(&mut self, text: &str, attrs: &Attributes) {
+        for c in text.chars() {
+            self.print(c, attrs.clone());
+        }
+    }
+
+    // Shift the scroll region up one line; lines leaving the top of the full
+    // primary screen are kept as history
+    fn scroll_up(&mut self) {
+        self.bump_generation();
+        // DECLRMM margins narrower than the screen scroll only the columns
+        // between them, and nothing reaches scrollback
+        if self.left_margin != 0 || self.right_margin != self.cols - 1 {
+            let (left, right) = (self.left_margin, self.right_margin);
+            for row in self.top_margin..=self.bottom_margin {
+                self.break_wide_pair(row, left);
+                self.break_wide_pair(row, right);
+            }
+            for row in self.top_margin..self.bottom_margin {
+                let (upper, lower) = self.grid.split_at_mut(row + 1);
+                upper[row][left..=right].clone_from_slice(&lower[0][left..=right]);
+            }
+            self.grid[self.bottom_margin][left..=right].fill(Cell::default());
+            return;
+        }
+        let line = self.grid.remove(self.top_margin);
+        self.grid.insert(self.bottom_margin, blank_row(self.cols));
+        self.line_attrs.remove(self.top_margin);
+        self.line_attrs
+            .insert(self.bottom_margin, LineAttribute::default());
+        self.wrapped.remove(self.top_margin);
+        self.wrapped.insert(self.bottom_margin, false);
+
+        if self.alt_screen || self.top_margin != 0 {
+            return;
+        }
+        if let Some(hook) = self.on_line_complete.as_mut() {
+            let mut text = String::new();
+            for cell in line.iter().filter(|cell| !cell.continuation) {
+                cell.push_text(&mut text);
+            }
+            hook(text.trim_end());
+        }
+        if self.scrollback_limit > 0 {
+            if self.scrollback.len() >= self.scrollback_limit {
+                self.scrollback.pop_front();
+            }
+            self.scrollback.push_back(line);
+        }
+    }
+
+    fn set_cursor_position(&mut self, row: u16, col: u16) {
+        self.bump_generation();
+        let row = if row == 0 { 1 } else { row };
+        let col = if col == 0 { 1 } else { col };
+
+        // Origin mode makes rows relative to, and confined by, the scroll region
+        let (top, bottom) = if self.origin_mode() {
+            (self.top_margin, self.bottom_margin)
+        } else {
+            (0, self.rows - 1)
+        };
+        self.cursor.row = (top + (row - 1) as usize).min(bottom);
+        self.set_column(col);
+    }
+
+    fn set_cursor_column(&mut self, col: u16) {
+        self.bump_generation();
+        self.set_column(if col == 0 { 1 } else { col });
+    }
+
+    // 1-based column, relative to and confined by the left/right margins under origin mode
+    fn set_column(&mut self, col: u16) {
+        self.wrap_pending = false;
+        let (left, right) = if self.origin_mode() {
+            (self.left_margin, self.right_margin)
+        } else {
+            (0, self.cols - 1)
+        };
+        self.cursor.col = (left + (col - 1) as usize).min(right);
+    }
+
+    fn origin_mode(&self) -> bool {
+        self.mode_enabled(true, 6)
+    }
+
+    fn autowrap(&self) -> bool {
+        self.mode_enabled(true, 7)
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.mode_enabled(true, 25)
+    }
+
+    // DECCKM (?1)
+    pub fn application_cursor_keys(&self) -> bool {
+        self.mode_enabled(true, 1)
+    }
+
+    // Bytes an arrow key press should send: SS3 forms under DECCKM, CSI otherwise
+    pub fn encode_arrow(&self, dir: Direction) -> Vec<u8> {
+        let last = match dir {
+            Direction::Up => b'A',
+            Direction::Down => b'B',
+            Direction::Right => b'C',
+            Direction::Left => b'D',
+        };
+        let intro = if self.application_cursor_keys() {
+            b'O'
+        } else {
+            b'['
+        };
+        vec![0x1b, intro, last]
+    }
+
+    // Mode 2026: the application is mid-update. A host should keep showing the
+    // last frame while this is set and repaint once it clears, so a
+    // half-drawn screen never reaches the display.
+    pub fn is_synchronized(&self) -> bool {
+        self.mode_enabled(true, 2026)
+    }
+
+    // Bytes to send the application when the host window gains or loses
+    // focus, if it asked for them with mode 1004
+    pub fn focus_event(&mut self, focused: bool) -> Option<Vec<u8>> {
+        if !self.mode_enabled(true, 1004) {
+            return None;
+        }
+        let report: &[u8] = if focused { b"\x1b[I" } else { b"\x1b[O" };
+        Some(report.to_vec())
+    }
+
+    // DECSTR: restore modes and margins to power-on values, keeping screen contents
+    fn soft_reset(&mut self) {
+        self.bump_generation();
+        self.modes.extend(DEFAULT_MODES);
+        self.application_keypad = false;
+        self.top_margin = 0;
+        self.bottom_margin = self.rows - 1;
+        self.reset_left_right_margins();
+        self.saved_cursor = Cursor::default();
+    }
+
+    fn set_scroll_region(&mut self, top: u16, bottom: u16) {
+        let top = top.max(1) as usize - 1;
+        let bottom = if bottom == 0 {
+            self.rows
+        } else {
+            (bottom as usize).min(self.rows)
+        } - 1;
+        if top >= bottom {
+            return;
+        }
+
+        self.top_margin = top;
+        self.bottom_margin = bottom;
+        self.set_cursor_position(1, 1);
+    }
+
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.responses)
+    }
+
+    pub fn peek_responses(&self) -> &[u8] {
+        &self.responses
+    }
+
+    pub fn clear_responses(&mut self) {
+        self.responses.clear();
+    }
+
+    pub fn set_cell_pixel_size(&mut self, width: usize, height: usize) {
+        self.cell_pixel_size = (width, height);
+    }
+
+    // XTWINOPS; only the size reports are answered
+    fn window_op(&mut self, params: &[u16]) {
+        let report = match params.first() {
+            Some(14) => {
+                let (width, height) = self.cell_pixel_size;
+                format!("\x1b[4;{};{}t", self.rows * height, self.cols * width)
+            }
+            Some(18) => format!("\x1b[8;{};{}t", self.rows, self.cols),
+            // Resize to rows;cols, where 0 keeps the current size
+            Some(8) => {
+                let param = |i: usize, current: usize| match params.get(i) {
+                    Some(0) | None => current,
+                    Some(&n) => n as usize,
+                };
+                self.resize(param(2, self.cols), param(1, self.rows));
+                return;
+            }
+            _ => return,
+        };
+        self.responses.extend_from_slice(report.as_bytes());
+    }
+
+    fn device_status_report(&mut self, code: u16) {
+        match code {
+            // Operating status: always OK
+            5 => self.responses.extend_from_slice(b"\x1b[0n"),
+            // Cursor position report, margin-relative under origin mode
+            6 => {
+                let (row, col) = if self.origin_mode() {
+                    (
+                        self.cursor.row.saturating_sub(self.top_margin),
+                        self.cursor.col.saturating_sub(self.left_margin),
+                    )
+                } else {
+                    (self.cursor.row, self.cursor.col)
+                };
+                // 1-based, unpadded decimal; computed in usize so wide grids cannot overflow
+                let report = format!("\x1b[{};{}R", row + 1, col + 1);
+                self.responses.extend_from_slice(report.as_bytes());
+            }
+            _ => {}
+        }
+    }
+
+    // Primary DA: a VT220 with ANSI color
+    fn device_attributes(&mut self) {
+        self.responses.extend_from_slice(b"\x1b[?62;22c");
+    }
+
+    // DECRQSS reply: DCS 1 $ r <setting> ST when understood, DCS 0 $ r ST otherwise
+    fn request_status(&mut self, setting: &[u8], attrs: &Attributes) {
+        let report = match setting {
+            b"m" => {
+                let mut params = vec![0];
+                params.extend(attrs.sgr_params());
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                format!("\x1bP1$r{}m\x1b\\", params.join(";"))
+            }
+            _ => String::from("\x1bP0$r\x1b\\"),
+        };
+        self.responses.extend_from_slice(report.as_bytes());
+    }
+
+    // XTGETTCAP reply: DCS 1 + r <name>=<value> ST per known capability, both
+    // hex-encoded. Like xterm, the first unknown name ends the reply with DCS 0 + r ST.
+    fn request_termcap(&mut self, names: &[u8]) {
+        for hex_name in names.split(|&b| b == b';') {
+            let Some(value) = decode_hex(hex_name).and_then(|name| termcap_value(&name)) else {
+                self.responses.extend_from_slice(b"\x1bP0+r\x1b\\");
+                return;
+            };
+            let report = format!(
+                "\x1bP1+r{}={}\x1b\\",
+                String::from_utf8_lossy(hex_name),
+                encode_hex(value.as_bytes())
+            );
+            self.responses.extend_from_slice(report.as_bytes());
+        }
+    }
+
+    // Host override; a later OSC 0/2 from the application replaces it again
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        self.title = title.into();
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn set_answerback(&mut self, answerback: &str) {
+        self.answerback = answerback.to_string();
+    }
+
+    pub fn set_da2_response(&mut self, response: &[u8]) {
+        self.da2_response = response.to_vec();
+    }
+
+    pub fn set_unit_id(&mut self, unit_id: &str) {
+        self.unit_id = unit_id.to_string();
+    }
+
+    fn cursor_up(&mut self, n: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.row = self.cursor.row.saturating_sub(n as usize);
+    }
+
+    fn cursor_down(&mut self, n: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.row = (self.cursor.row + n as usize).min(self.rows - 1);
+    }
+
+    fn cursor_forward(&mut self, n: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.col = (self.cursor.col + n as usize).min(self.cols - 1);
+    }
+
+    fn cursor_back(&mut self, n: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.col = self.cursor.col.saturating_sub(n as usize);
+    }
+
+    fn carriage_return(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.col = 0;
+    }
+
+    fn line_feed(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        // Only the bottom margin scrolls; below the region the cursor just moves down
+        if self.cursor.row == self.bottom_margin {
+            self.scroll_up();
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+        self.cursor.col = 0;
+    }
+
+    fn tab(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        // With no stop ahead the cursor goes to the last column
+        self.cursor.col = (self.cursor.col + 1..self.cols)
+            .find(|&col| self.tab_stops[col])
+            .unwrap_or(self.cols - 1);
+    }
+
+    fn set_tab_stop(&mut self) {
+        self.tab_stops[self.cursor.col] = true;
+    }
+
+    pub fn reset_tab_stops_default(&mut self) {
+        self.tab_stops = default_tab_stops(self.cols);
+    }
+
+    // TBC: 0 clears the stop at the cursor, 3 clears every stop
+    pub fn clear_tab_stops(&mut self, mode: u16) {
+        match mode {
+            0 => self.tab_stops[self.cursor.col] = false,
+            3 => self.tab_stops.fill(false),
+            _ => {}
+        }
+    }
+
+    fn backspace(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor.col = self.cursor.col.saturating_sub(1);
+    }
+
+    pub fn cursor_style(&self) -> u16 {
+        self.cursor_style
+    }
+
+    // Odd styles blink, and 0 is xterm's blinking block default
+    pub fn cursor_blinks(&self) -> bool {
+        self.cursor_style == 0 || self.cursor_style % 2 == 1
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = self.cursor.clone();
+    }
+
+    fn restore_cursor(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        self.cursor = self.saved_cursor.clone();
+    }
+
+    fn set_left_right_margins(&mut self, left: u16, right: u16) {
+        let left = left.max(1) as usize - 1;
+        let right = if right == 0 {
+            self.cols
+        } else {
+            (right as usize).min(self.cols)
+        } - 1;
+        if left >= right {
+            return;
+        }
+
+        self.left_margin = left;
+        self.right_margin = right;
+        self.set_cursor_position(1, 1);
+    }
+
+    fn reset_left_right_margins(&mut self) {
+        self.left_margin = 0;
+        self.right_margin = self.cols - 1;
+    }
+
+    // ICH shifts the rest of the margin area right; cells pushed past the right margin are lost
+    fn insert_chars(&mut self, n: u16) {
+        let col = self.cursor.col;
+        if col < self.left_margin || col > self.right_margin {
+            return;
+        }
+        self.bump_generation();
+        self.wrap_pending = false;
+
+        let span = &mut self.grid[self.cursor.row][col..=self.right_margin];
+        let n = (n.max(1) as usize).min(span.len());
+        span.rotate_right(n);
+        span[..n].fill(Cell::default());
+    }
+
+    // DCH pulls the rest of the margin area left and blanks the vacated cells
+    fn delete_chars(&mut self, n: u16) {
+        let col = self.cursor.col;
+        if col < self.left_margin || col > self.right_margin {
+            return;
+        }
+        self.bump_generation();
+        self.wrap_pending = false;
+
+        let span = &mut self.grid[self.cursor.row][col..=self.right_margin];
+        let n = (n.max(1) as usize).min(span.len());
+        span.rotate_left(n);
+        let len = span.len();
+        span[len - n..].fill(Cell::default());
+    }
+
+    fn clear_cell(&mut self, row: usize, col: usize) {
+        self.grid[row][col] = Cell::default();
+    }
+
+    // ED always covers the full screen: unlike ICH/DCH and scrolling, it
+    // ignores both the DECSTBM region and the DECSLRM margins
+    fn erase_display(&mut self, mode: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        // Both partial forms include the cursor cell itself
+        match mode {
+            0 => {
+                for col in self.cursor.col..self.cols {
+                    self.clear_cell(self.cursor.row, col);
+                }
+                for row in (self.cursor.row + 1)..self.rows {
+                    for col in 0..self.cols {
+                        self.clear_cell(row, col);
+                    }
+                }
+                self.wrapped[self.cursor.row..].fill(false);
+            }
+            1 => {
+                for row in 0..self.cursor.row {
+                    for col in 0..self.cols {
+                        self.clear_cell(row, col);
+                    }
+                }
+                for col in 0..=self.cursor.col {
+                    self.clear_cell(self.cursor.row, col);
+                }
+                self.wrapped[..self.cursor.row].fill(false);
+            }
+            2 | 3 => {
+                for row in 0..self.rows {
+                    for col in 0..self.cols {
+                        self.clear_cell(row, col);
+                    }
+                }
+                self.wrapped.fill(false);
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        let row = self.cursor.row;
+        match mode {
+            0 => {
+                for col in self.cursor.col..self.cols {
+                    self.clear_cell(row, col);
+                }
+                self.wrapped[row] = false;
+            }
+            1 => {
+                for col in 0..=self.cursor.col {
+                    self.clear_cell(row, col);
+                }
+            }
+            2 => {
+                for col in 0..self.cols {
+                    self.clear_cell(row, col);
+                }
+                self.wrapped[row] = false;
+            }
+            _ => {}
+        }
+    }
+
+    // Resolve a rectangle to inclusive grid bounds, relative to the margins
+    // under origin mode and clamped to the screen; None when empty
+    fn rect_bounds(&self, rect: Rect) -> Option<(usize, usize, usize, usize)> {
+        let (top_edge, left_edge, bottom_edge, right_edge) = if self.origin_mode() {
+            (
+                self.top_margin,
+                self.left_margin,
+                self.bottom_margin,
+                self.right_margin,
+            )
+        } else {
+            (0, 0, self.rows - 1, self.cols - 1)
+        };
+        let corner = |value: u16, origin: usize, edge: usize| match value {
+            0 => edge,
+            n => (origin + n as usize - 1).min(edge),
+        };
+
+        let top = corner(rect.top.max(1), top_edge, bottom_edge);
+        let left = corner(rect.left.max(1), left_edge, right_edge);
+        let bottom = corner(rect.bottom, top_edge, bottom_edge);
+        let right = corner(rect.right, left_edge, right_edge);
+        (top <= bottom && left <= right).then_some((top, left, bottom, right))
+    }
+
+    // DECERA
+    pub fn erase_rect(&mut self, rect: Rect) {
+        let Some((top, left, bottom, right)) = self.rect_bounds(rect) else {
+            return;
+        };
+        self.bump_generation();
+        for row in top..=bottom {
+            for col in left..=right {
+                self.break_wide_pair(row, col);
+                self.clear_cell(row, col);
+            }
+        }
+    }
+
+    // DECCRA; the source is buffered first so overlapping copies are safe,
+    // and whatever lands past the screen edge is dropped
+    pub fn copy_rect(&mut self, src: Rect, top: u16, left: u16) {
+        let Some((src_top, src_left, src_bottom, src_right)) = self.rect_bounds(src) else {
+            return;
+        };
+        let dest = Rect {
+            top,
+            left,
+            bottom: 0,
+            right: 0,
+        };
+        let Some((dest_top, dest_left, dest_bottom, dest_right)) = self.rect_bounds(dest) else {
+            return;
+        };
+
+        self.bump_generation();
+        let block: Vec<Vec<Cell>> = self.grid[src_top..=src_bottom]
+            .iter()
+            .map(|row| row[src_left..=src_right].to_vec())
+            .collect();
+        for (row, cells) in (dest_top..=dest_bottom).zip(&block) {
+            let end = (dest_left + cells.len() - 1).min(dest_right);
+            self.break_wide_pair(row, dest_left);
+            self.break_wide_pair(row, end);
+            let line = &mut self.grid[row];
+            line[dest_left..=end].clone_from_slice(&cells[..=end - dest_left]);
+            // Halves of wide glyphs the source edges cut through don't survive the copy
+            if line[dest_left].continuation {
+                line[dest_left] = Cell::default();
+            }
+            if char_width(line[end].character) == 2
+                && line.get(end + 1).is_none_or(|next| !next.continuation)
+            {
+                line[end] = Cell::default();
+            }
+        }
+    }
+
+    // DECFRA; only single-column characters can tile a rectangle
+    pub fn fill_rect(&mut self, character: char, attrs: &Attributes, rect: Rect) {
+        if char_width(character) != 1 {
+            return;
+        }
+        let Some((top, left, bottom, right)) = self.rect_bounds(rect) else {
+            return;
+        };
+        self.bump_generation();
+        for row in top..=bottom {
+            for col in left..=right {
+                self.write_cell(row, col, character, attrs.clone());
+            }
+        }
+    }
+
+    // DECDWL/DECDHL state of a row, for renderers that scale it
+    pub fn line_attribute(&self, row: usize) -> LineAttribute {
+        self.line_attrs.get(row).copied().unwrap_or_default()
+    }
+
+    fn set_line_attribute(&mut self, attr: LineAttribute) {
+        self.bump_generation();
+        self.line_attrs[self.cursor.row] = attr;
+    }
+
+    // Double-width and double-height rows only have room for half the columns
+    fn visible_cols(&self, row: usize) -> usize {
+        match self.line_attribute(row) {
+            LineAttribute::SingleWidth => self.cols,
+            _ => self.cols.div_ceil(2),
+        }
+    }
+
+    // DECALN fills the screen with 'E' so the operator can check alignment
+    fn alignment_test(&mut self) {
+        self.bump_generation();
+        self.wrap_pending = false;
+        for row in self.grid.iter_mut() {
+            for cell in row.iter_mut() {
+                *cell = Cell {
+                    character: 'E',
+                    ..Cell::default()
+                };
+            }
+        }
+        self.line_attrs.fill(LineAttribute::SingleWidth);
+        self.wrapped.fill(false);
+        self.cursor = Cursor::default();
+    }
+
+    fn enter_alt_screen(&mut self, save_cursor: bool, clear: bool) {
+        if self.alt_screen {
+            return;
+        }
+        self.bump_generation();
+        self.wrap_pending = false;
+        if save_cursor {
+            self.saved_cursor = self.cursor.clone();
+        }
+        // Only the buffers switch; the SGR pen lives in the parser and carries over as in xterm
+        self.swap_buffers();
+        self.alt_screen = true;
+        if clear {
+            self.erase_display(2);
+        }
+    }
+
+    fn exit_alt_screen(&mut self, restore_cursor: bool, clear: bool) {
+        if !self.alt_screen {
+            return;
+        }
+        self.bump_generation();
+        if clear {
+            self.erase_display(2);
+        }
+        self.swap_buffers();
+        self.alt_screen = false;
+        self.wrap_pending = false;
+        if restore_cursor {
+            self.cursor = self.saved_cursor.clone();
+        }
+    }
+
+    // Each buffer keeps its own per-row state alongside its cells
+    fn swap_buffers(&mut self) {
+        core::mem::swap(&mut self.grid, &mut self.inactive_grid);
+        core::mem::swap(&mut self.line_attrs, &mut self.inactive_line_attrs);
+        core::mem::swap(&mut self.wrapped, &mut self.inactive_wrapped);
+    }
+
+    pub fn mode_enabled(&self, private: bool, number: u16) -> bool {
+        self.modes.get(&(private, number)).copied().unwrap_or(false)
+    }
+
+    fn set_mode(&mut self, private: bool, number: u16, enabled: bool) {
+        self.modes.insert((private, number), enabled);
+
+        match (private, number, enabled) {
+            // 47 switches buffers only, 1047 also clears the alt buffer on the way
+            // out, and 1049 saves the cursor and starts blank instead
+            (true, 47 | 1047, true) => self.enter_alt_screen(false, false),
+            (true, 47, false) => self.exit_alt_screen(false, false),
+            (true, 1047, false) => self.exit_alt_screen(false, true),
+            (true, 1049, true) => self.enter_alt_screen(true, true),
+            (true, 1049, false) => self.exit_alt_screen(true, false),
+            (true, 1000 | 1002 | 1003, _) => self.set_mouse_tracking(number, enabled),
+            (true, 69, false) => self.reset_left_right_margins(),
+            // DECOM homes the cursor whenever it changes
+            (true, 6, _) => self.set_cursor_position(1, 1),
+            _ => {}
+        }
+    }
+
+    pub fn save_modes(&mut self, modes: &[u16]) {
+        for &mode in modes {
+            self.saved_modes.insert(mode, self.mode_enabled(true, mode));
+        }
+    }
+
+    // Goes through `set_mode` so side effects such as the alt screen switch replay
+    pub fn restore_modes(&mut self, modes: &[u16]) {
+        for &mode in modes {
+            if let Some(&enabled) = self.saved_modes.get(&mode) {
+                self.set_mode(true, mode, enabled);
+            }
+        }
+    }
+
+    fn reset_palette(&mut self, indices: &[u8]) {
+        self.bump_generation();
+        let defaults = default_palette();
+        if indices.is_empty() {
+            self.palette = defaults;
+        }
+        for &index in indices {
+            self.palette[index as usize] = defaults[index as usize];
+        }
+    }
+
+    fn set_mouse_tracking(&mut self, number: u16, enabled: bool) {
+        self.mouse_tracking = match (number, enabled) {
+            (1000, true) => MouseTracking::Click,
+            (1002, true) => MouseTracking::ButtonEvent,
+            (1003, true) => MouseTracking::AnyEvent,
+            _ => MouseTracking::Off,
+        };
+        // Keep the registry in step so only the active level reads as set
+        for other in [1000, 1002, 1003] {
+            self.modes.insert((true, other), enabled && other == number);
+        }
+    }
+
+    // For input encoders choosing between numeric and application keypad codes
+    pub fn application_keypad(&self) -> bool {
+        self.application_keypad
+    }
+
+    // Value an application set with XTMODKEYS, or None while at the default
+    pub fn modify_keys(&self, resource: u16) -> Option<u16> {
+        self.modify_keys.get(&resource).copied()
+    }
+
+    pub fn mouse_tracking(&self) -> MouseTracking {
+        self.mouse_tracking
+    }
+
+    // Maps a pixel position in the host window to the 1-based (col, row) under
+    // it, clamped to the grid; a zero cell size is treated as one pixel
+    pub fn pixel_to_cell(&self, x: u32, y: u32, cell_w: u32, cell_h: u32) -> (u16, u16) {
+        let clamp = |pixel: u32, size: u32, cells: usize| {
+            let index = (pixel / size.max(1)) as usize;
+            (index.min(cells - 1) + 1).min(u16::MAX as usize) as u16
+        };
+        (clamp(x, cell_w, self.cols), clamp(y, cell_h, self.rows))
+    }
+
+    pub fn process_event(&mut self, event: &TerminalEvent) {
+        match event {
+            TerminalEvent::Print { char, attrs } => {
+                self.print(*char, attrs.clone());
+            }
+            TerminalEvent::PrintStr { text, attrs } => {
+                self.print_str(text, attrs);
+            }
+            TerminalEvent::Linefeed => {
+                self.line_feed();
+            }
+            TerminalEvent::CarriageReturn => {
+                self.carriage_return();
+            }
+            TerminalEvent::Backspace => {
+                self.backspace();
+            }
+            TerminalEvent::Tab => {
+                self.tab();
+            }
+            TerminalEvent::Bell => {}
+            TerminalEvent::Enquiry => {
+                self.responses.extend_from_slice(self.answerback.as_bytes());
+            }
+            TerminalEvent::CursorPosition { row, col } => {
+                self.set_cursor_position(*row, *col);
+            }
+            TerminalEvent::CursorUp(n) => {
+                self.cursor_up(*n);
+            }
+            TerminalEvent::CursorDown(n) => {
+                self.cursor_down(*n);
+            }
+            TerminalEvent::CursorForward(n) => {
+                self.cursor_forward(*n);
+            }
+            TerminalEvent::CursorBack(n) => {
+                self.cursor_back(*n);
+            }
+            TerminalEvent::CursorColumn(col) => {
+                self.set_cursor_column(*col);
+            }
+            TerminalEvent::EraseDisplay(mode) => {
+                self.erase_display(*mode);
+            }
+            TerminalEvent::EraseLine(mode) => {
+                self.erase_line(*mode);
+            }
+            TerminalEvent::SetMode(modes) => {
+                for mode in modes {
+                    self.set_mode(false, *mode, true);
+                }
+            }
+            TerminalEvent::ResetMode(modes) => {
+                for mode in modes {
+                    self.set_mode(false, *mode, false);
+                }
+            }
+            TerminalEvent::SetPrivateMode(modes) => {
+                for mode in modes {
+                    self.set_mode(true, *mode, true);
+                }
+            }
+            TerminalEvent::ResetPrivateMode(modes) => {
+                for mode in modes {
+                    self.set_mode(true, *mode, false);
+                }
+            }
+            TerminalEvent::AlignmentTest => {
+                self.alignment_test();
+            }
+            TerminalEvent::ApplicationKeypad(enabled) => {
+                self.application_keypad = *enabled;
+            }
+            TerminalEvent::ModifyKeys { resource, value } => match value {
+                Some(value) => {
+                    self.modify_keys.insert(*resource, *value);
+                }
+                None => {
+                    self.modify_keys.remove(resource);
+                }
+            },
+            TerminalEvent::LineAttribute(attr) => {
+                self.set_line_attribute(*attr);
+            }
+            TerminalEvent::SetPaletteColor { index, rgb } => {
+                self.bump_generation();
+                self.palette[*index as usize] = *rgb;
+            }
+            TerminalEvent::ResetPaletteColors(indices) => {
+                self.reset_palette(indices);
+            }
+            TerminalEvent::SetPointerShape(shape) => {
+                self.pointer_shape = Some(shape.clone());
+            }
+            TerminalEvent::SetTitle(title) => {
+                self.title = title.clone();
+            }
+            TerminalEvent::Hyperlink { id, uri } => {
+                self.set_hyperlink(id, uri);
+            }
+            TerminalEvent::InsertChars(n) => {
+                self.insert_chars(*n);
+            }
+            TerminalEvent::Repeat(n) => {
+                self.repeat(*n);
+            }
+            TerminalEvent::DeleteChars(n) => {
+                self.delete_chars(*n);
+            }
+            TerminalEvent::SetMarginsOrSaveCursor { left, right } => {
+                if self.mode_enabled(true, 69) {
+                    self.set_left_right_margins(*left, *right);
+                } else {
+                    self.save_cursor();
+                }
+            }
+            TerminalEvent::RestoreCursor => {
+                self.restore_cursor();
+            }
+            TerminalEvent::SavePrivateModes(modes) => {
+                self.save_modes(modes);
+            }
+            TerminalEvent::RestorePrivateModes(modes) => {
+                self.restore_modes(modes);
+            }
+            TerminalEvent::SetScrollRegion { top, bottom } => {
+                self.set_scroll_region(*top, *bottom);
+            }
+            TerminalEvent::DeviceStatusReport(code) => {
+                self.device_status_report(*code);
+            }
+            TerminalEvent::EraseRect(rect) => {
+                self.erase_rect(*rect);
+            }
+            TerminalEvent::CopyRect { src, top, left } => {
+                self.copy_rect(*src, *top, *left);
+            }
+            TerminalEvent::FillRect {
+                character,
+                attrs,
+                rect,
+            } => {
+                self.fill_rect(*character, attrs, *rect);
+            }
+            TerminalEvent::RequestStatus { setting, attrs } => {
+                self.request_status(setting, attrs);
+            }
+            TerminalEvent::RequestTermcap(names) => {
+                self.request_termcap(names);
+            }
+            TerminalEvent::DeviceAttributes => {
+                self.device_attributes();
+            }
+            TerminalEvent::SecondaryDeviceAttributes => {
+                self.responses.extend_from_slice(&self.da2_response);
+            }
+            TerminalEvent::TertiaryDeviceAttributes => {
+                let report = format!("\x1bP!|{}\x1b\\", self.unit_id);
+                self.responses.extend_from_slice(report.as_bytes());
+            }
+            TerminalEvent::WindowOp(params) => {
+                self.window_op(params);
+            }
+            TerminalEvent::SoftReset => {
+                self.soft_reset();
+            }
+            TerminalEvent::SetCursorStyle(style) => {
+                self.bump_generation();
+                self.cursor_style = *style;
+            }
+            TerminalEvent::SetTabStop => {
+                self.set_tab_stop();
+            }
+            TerminalEvent::ClearTabStops(mode) => {
+                self.clear_tab_stops(*mode);
+            }
+            TerminalEvent::ResetTabStops => {
+                self.reset_tab_stops_default();
+            }
+            TerminalEvent::UnhandledCsi { .. }
+            | TerminalEvent::UnhandledEsc(_)
+            | TerminalEvent::UnhandledSgr(_)
+            | TerminalEvent::Osc { .. }
+            | TerminalEvent::InlineImage { .. }
+            | TerminalEvent::Dcs { .. }
+            | TerminalEvent::OversizedPayload { .. } => {}
+        }
+    }
+
+    // Parse raw PTY output and apply it to the grid
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parser.advance(&mut self.vte, bytes);
+        let mut events = core::mem::take(&mut self.parser.events);
+        self.process_events(&events);
+        // Hand the allocation back for the next feed
+        events.clear();
+        self.parser.events = events;
+    }
+
+    // For proxies: feed output and get back only the replies it provoked,
+    // leaving earlier pending responses queued
+    pub fn feed_collecting_responses(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let start = self.responses.len();
+        self.feed(bytes);
+        self.responses.split_off(start)
+    }
+
+    pub fn feed_str(&mut self, s: &str) {
+        self.feed(s.as_bytes());
+    }
+
+    // Rebuild state from recorded chunks as fast as possible
+    pub fn replay(&mut self, events: impl Iterator<Item = (Duration, Vec<u8>)>) {
+        for (_, data) in events {
+            self.feed(&data);
+        }
+    }
+
+    // Like `replay`, but sleeps so each chunk lands at its recorded offset
+    #[cfg(feature = "std")]
+    pub fn replay_realtime(&mut self, events: impl Iterator<Item = (Duration, Vec<u8>)>) {
+        let start = std::time::Instant::now();
+        for (when, data) in events {
+            if let Some(wait) = when.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            self.feed(&data);
+        }
+    }
+
+    pub fn process_events(&mut self, events: &[TerminalEvent]) {
+        for event in events {
+            self.process_event(event);
+        }
+    }
+
+    // Apply a batch and hand back just the replies it produced, leaving any
+    // earlier pending responses queued
+    pub fn apply_events(&mut self, events: &[TerminalEvent]) -> Vec<u8> {
+        let start = self.responses.len();
+        self.process_events(events);
+        self.responses.split_off(start)
+    }
+
+    // Current 256-color palette, including OSC 4 changes
+    pub fn palette_rgb(&self) -> [(u8, u8, u8); 256] {
+        self.palette
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Scrollback plus visible rows, e.g. for sizing a scrollbar
+    pub fn total_lines(&self) -> usize {
+        self.scrollback.len() + self.rows
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&Cell> {
+        self.grid.get(row)?.get(col)
+    }
+
+    pub fn attrs_at(&self, row: usize, col: usize) -> Option<&Attributes> {
+        self.cell(row, col).map(Cell::attrs)
+    }
+
+    fn set_hyperlink(&mut self, id: &Option<String>, uri: &str) {
+        if uri.is_empty() {
+            self.hyperlink = None;
+            return;
+        }
+        let link = (id.clone(), uri.to_string());
+        let index = match self.link_index.get(&link) {
+            Some(&index) => index,
+            None => {
+                if self.links.len() >= self.link_gc_at {
+                    self.collect_links();
+                }
+                let index = self.links.len() as u32;
+                self.link_index.insert(link.clone(), index);
+                self.links.push(link);
+                index
+            }
+        };
+        self.hyperlink = Some(index);
+    }
+
+    // Drops links no cell (on either screen, in scrollback or the damage
+    // snapshot) still refers to, renumbering the survivors
+    fn collect_links(&mut self) {
+        let mut remap: Vec<Option<u32>> = vec![None; self.links.len()];
+        let mut live = Vec::new();
+        let mut renumber = |link: &mut Option<u32>| {
+            if let Some(old) = *link {
+                let new = *remap[old as usize].get_or_insert_with(|| {
+                    live.push(old);
+                    live.len() as u32 - 1
+                });
+                *link = Some(new);
+            }
+        };
+        renumber(&mut self.hyperlink);
+        let rows = self
+            .grid
+            .iter_mut()
+            .chain(self.inactive_grid.iter_mut())
+            .chain(self.scrollback.iter_mut())
+            .chain(self.damage_base.iter_mut());
+        for cell in rows.flatten() {
+            renumber(&mut cell.link);
+        }
+
+        let mut old_links: Vec<Option<(Option<String>, String)>> = core::mem::take(&mut self.links)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.links = live
+            .into_iter()
+            .filter_map(|old| old_links[old as usize].take())
+            .collect();
+        self.link_index = self
+            .links
+            .iter()
+            .enumerate()
+            .map(|(index, link)| (link.clone(), index as u32))
+            .collect();
+        self.link_gc_at = LINK_GC_THRESHOLD.max(self.links.len() * 2);
+    }
+
+    // Distinct links on the visible grid in order of first appearance, each
+    // with the (row, columns) runs of adjacent cells it covers, for building
+    // clickable areas. A link that wraps or repeats is listed once.
+    pub fn hyperlinks(&self) -> Vec<(LinkRuns, String)> {
+        let mut found: Vec<(LinkRuns, String)> = Vec::new();
+        let mut position: BTreeMap<u32, usize> = BTreeMap::new();
+        for (row, line) in self.grid.iter().enumerate() {
+            let mut col = 0;
+            while col < line.len() {
+                let Some(link) = line[col].link else {
+                    col += 1;
+                    continue;
+                };
+                let start = col;
+                while col < line.len() && line[col].link == Some(link) {
+                    col += 1;
+                }
+                let index = *position.entry(link).or_insert_with(|| {
+                    found.push((Vec::new(), self.links[link as usize].1.clone()));
+                    found.len() - 1
+                });
+                found[index].0.push((row, start..col));
+            }
+        }
+        found
+    }
+
+    // True when every visible cell is a default-styled space, e.g. after a clear
+    pub fn is_blank(&self) -> bool {
+        let blank = Cell::default();
+        self.grid.iter().flatten().all(|cell| *cell == blank)
+    }
+
+    // Visible content equality for snapshot tests: trailing blank cells,
+    // trailing blank rows and the cursor are all ignored
+    pub fn content_eq(&self, other: &Terminal) -> bool {
+        fn trimmed(row: &[Cell]) -> &[Cell] {
+            let blank = Cell::default();
+            let end = row
+                .iter()
+                .rposition(|cell| *cell != blank)
+                .map_or(0, |i| i + 1);
+            &row[..end]
+        }
+
+        let rows = self.grid.len().max(other.grid.len());
+        (0..rows).all(|row| {
+            let ours = self.grid.get(row).map_or(&[][..], |r| trimmed(r));
+            let theirs = other.grid.get(row).map_or(&[][..], |r| trimmed(r));
+            ours == theirs
+        })
+    }
+
+    // Hash of what the visible cells show, so scrollback, the cursor and how
+    // the link table happens to be numbered do not matter. Lengths are written
+    // as u64 so the value does not depend on the platform's usize.
+    pub fn screen_hash(&self) -> u64 {
+        fn write_str(hasher: &mut Fnv1a, s: &str) {
+            hasher.write_u64(s.len() as u64);
+            hasher.write(s.as_bytes());
+        }
+
+        let mut hasher = Fnv1a::default();
+        for row in &self.grid {
+            hasher.write_u64(row.len() as u64);
+            for cell in row {
+                hasher.write_u32(cell.character as u32);
+                write_str(&mut hasher, &cell.combining);
+                hasher.write_u8(cell.continuation as u8);
+                let params = cell.attrs.sgr_params();
+                hasher.write_u64(params.len() as u64);
+                for param in params {
+                    hasher.write_u16(param);
+                }
+                let uri = cell.link.map_or("", |link| &self.links[link as usize].1);
+                write_str(&mut hasher, uri);
+            }
+        }
+        hasher.finish()
+    }
+
+    // Drive a backend over the visible grid, honouring double-width rows
+    pub fn render_with<R: Renderer>(&self, mut renderer: R) -> R::Output {
+        for (row_idx, row) in self.grid.iter().enumerate() {
+            renderer.begin_line(row_idx);
+            for cell in row.iter().take(self.visible_cols(row_idx)) {
+                renderer.cell(cell);
+            }
+            renderer.end_line(row_idx);
+        }
+        renderer.finish()
+    }
+
+    pub fn render_content(&self) -> String {
+        self.render_with(PlainRenderer::default())
+    }
+
+    pub fn render_with_options(&self, options: RenderOptions) -> String {
+        self.render_with(PlainRenderer {
+            options,
+            ..PlainRenderer::default()
+        })
+    }
+
+    pub fn render_to_ansi(&self) -> String {
+        self.render_with(AnsiRenderer::default())
+    }
+
+    // ANSI output with every color resolved through the palette to `38;2`/`48;2`
+    // RGB, so it looks the same on any truecolor terminal
+    pub fn render_to_truecolor_ansi(&self) -> String {
+        self.render_with(AnsiRenderer {
+            palette: Some(self.palette),
+            ..AnsiRenderer::default()
+        })
+    }
+
+    // ANSI output with the cursor cell shown inverted while DECTCEM has it visible
+    pub fn render_to_ansi_with_cursor(&self) -> String {
+        let cursor = self
+            .cursor_visible()
+            .then_some((self.cursor.row, self.cursor.col));
+        self.render_with(AnsiRenderer {
+            cursor,
+            ..AnsiRenderer::default()
+        })
+    }
+
+    // One row, prefixed with a reset so it does not depend on the receiver's pen;
+    // out-of-range rows render as nothing
+    pub fn render_row_to_ansi(&self, row: usize) -> String {
+        let Some(cells) = self.grid.get(row) else {
+            return String::new();
+        };
+        let mut renderer = AnsiRenderer::default();
+        renderer.begin_line(row);
+        for cell in cells.iter().take(self.visible_cols(row)) {
+            renderer.cell(cell);
+        }
+        renderer.end_line(row);
+
+        let mut output = renderer.finish();
+        output.pop();
+        format!("\x1b[0m{output}")
+    }
+
+    pub fn render_to_html(&self) -> String {
+        self.render_with(HtmlRenderer::new(self.palette))
+    }
+
+    // Last OSC 22 shape, for the host to map onto its own cursor set
+    pub fn pointer_shape(&self) -> Option<&str> {
+        self.pointer_shape.as_deref()
+    }
+
+    fn resolve_color(&self, color: Color, default: Rgb) -> Rgb {
+        resolve_color(&self.palette, color, default)
+    }
+
+    // Row-major dump of the visible grid for external renderers
+    pub fn export_cells(&self) -> Vec<CellExport> {
+        self.grid
+            .iter()
+            .flatten()
+            .map(|cell| self.export_cell(cell))
+            .collect()
+    }
+
+    fn export_cell(&self, cell: &Cell) -> CellExport {
+        let (fr, fg, fb) = self.resolve_color(cell.attrs.foreground, DEFAULT_FOREGROUND);
+        let (br, bg, bb) = self.resolve_color(cell.attrs.background, DEFAULT_BACKGROUND);
+        let flags = [
+            (cell.attrs.bold, CellExport::BOLD),
+            (cell.attrs.italic, CellExport::ITALIC),
+            (cell.attrs.underline, CellExport::UNDERLINE),
+            (cell.attrs.inverse, CellExport::INVERSE),
+            (cell.continuation, CellExport::CONTINUATION),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, bit)| flags | bit);
+
+        CellExport {
+            character: cell.character as u32,
+            fg: [fr, fg, fb],
+            bg: [br, bg, bb],
+            flags,
+        }
+    }
+
+    // One row as (text, fg, bg, flags) per cell with colors resolved, using
+    // the same flag bits as the cell export. Continuation cells have empty
+    // text; a row outside the grid yields nothing.
+    pub fn row_rgb(&self, row: usize) -> Vec<(String, Rgb, Rgb, u8)> {
+        let Some(line) = self.grid.get(row) else {
+            return Vec::new();
+        };
+        line.iter()
+            .map(|cell| {
+                let export = self.export_cell(cell);
+                let mut text = String::new();
+                if !cell.continuation {
+                    cell.push_text(&mut text);
+                }
+                let [fr, fg, fb] = export.fg;
+                let [br, bg, bb] = export.bg;
+                (text, (fr, fg, fb), (br, bg, bb), export.flags)
+            })
+            .collect()
+    }
+
+    pub fn segments(&self) -> Vec<Vec<Segment>> {
+        self.grid.iter().map(|row| row_segments(row)).collect()
+    }
+
+    // JSON array of rows that differ from `prev`, each as styled segments with
+    // resolved colors. A size change leads with a resize marker and sends every row.
+    pub fn json_diff(&self, prev: &Terminal) -> String {
+        let resized = (self.cols, self.rows) != (prev.cols, prev.rows);
+        let mut entries: Vec<String> = Vec::new();
+        if resized {
+            entries.push(format!(
+                "{{\"resize\":{{\"cols\":{},\"rows\":{}}}}}",
+                self.cols, self.rows
+            ));
+        }
+
+        for (index, row) in self.grid.iter().enumerate() {
+            if !resized && prev.grid[index] == *row {
+                continue;
+            }
+            let segments: Vec<String> = row_segments(row)
+                .iter()
+                .map(|segment| self.segment_json(segment))
+                .collect();
+            entries.push(format!(
+                "{{\"row\":{index},\"segments\":[{}]}}",
+                segments.join(",")
+            ));
+        }
+        format!("[{}]", entries.join(","))
+    }
+
+    fn segment_json(&self, segment: &Segment) -> String {
+        let attrs = &segment.attrs;
+        let (fr, fg, fb) = self.resolve_color(attrs.foreground, DEFAULT_FOREGROUND);
+        let (br, bg, bb) = self.resolve_color(attrs.background, DEFAULT_BACKGROUND);
+        let mut json = format!(
+            "{{\"text\":\"{}\",\"fg\":\"#{fr:02x}{fg:02x}{fb:02x}\",\"bg\":\"#{br:02x}{bg:02x}{bb:02x}\"",
+            escape_json(&segment.text)
+        );
+        for (set, name) in [
+            (attrs.bold, "bold"),
+            (attrs.italic, "italic"),
+            (attrs.underline, "underline"),
+            (attrs.inverse, "inverse"),
+        ] {
+            if set {
+                json.push_str(&format!(",\"{name}\":true"));
+            }
+        }
+        json.push('}');
+        json
+    }
+
+    // Window over scrollback followed by the visible screen, clamped to what exists
+    pub fn render_viewport(&self, top_line: usize, rows: usize) -> Vec<Vec<Cell>> {
+        let total = self.scrollback.len() + self.grid.len();
+        let rows = rows.min(total);
+        let top_line = top_line.min(total - rows);
+
+        self.scrollback
+            .iter()
+            .chain(self.grid.iter())
+            .skip(top_line)
+            .take(rows)
+            .cloned()
+            .collect()
+    }
+
+    // Absolute line numbers count scrollback first, then the visible rows
+    pub fn absolute_line(&self, viewport_row: usize) -> usize {
+        self.scrollback.len() + viewport_row
+    }
+
+    pub fn viewport_row_of(&self, absolute: usize) -> Option<usize> {
+        absolute
+            .checked_sub(self.scrollback.len())
+            .filter(|row| *row < self.rows)
+    }
+
+    // Full text of the autowrapped line containing `row`, as copy-on-double-click wants it
+    pub fn logical_line(&self, row: usize) -> String {
+        if row >= self.rows {
+            return String::new();
+        }
+        let mut first = row;
+        while first > 0 && self.wrapped[first - 1] {
+            first -= 1;
+        }
+        let mut last = row;
+        while last + 1 < self.rows && self.wrapped[last] {
+            last += 1;
+        }
+
+        let mut line = String::new();
+        for cell in self.grid[first..=last].iter().flatten() {
+            if !cell.continuation {
+                cell.push_text(&mut line);
+            }
+        }
+        line.trim_end().to_string()
+    }
+
+    // Compact fixture format: one line per row of `count*char` runs, each
+    // followed by `[sgr]` when styled. The char carries any combining marks,
+    // and wide characters imply their continuation; hyperlinks are not kept.
+    pub fn to_rle(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        for row in &self.grid {
+            let mut line = String::new();
+            let mut cells = row.iter().filter(|cell| !cell.continuation).peekable();
+            while let Some(cell) = cells.next() {
+                let mut count = 1;
+                while cells
+                    .next_if(|next| {
+                        next.character == cell.character
+                            && next.combining == cell.combining
+                            && next.attrs == cell.attrs
+                    })
+                    .is_some()
+                {
+                    count += 1;
+                }
+                line.push_str(&format!("{count}*"));
+                cell.push_text(&mut line);
+                if !cell.attrs.is_default() {
+                    let params: Vec<String> = cell
+                        .attrs
+                        .sgr_params()
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect();
+                    line.push_str(&format!("[{}]", params.join(";")));
+                }
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    // Inverse of `to_rle`; None if the input is malformed or rows differ in width
+    pub fn from_rle(rle: &str) -> Option<Terminal> {
+        let mut rows: Vec<Vec<Cell>> = Vec::new();
+        for line in rle.split('\n') {
+            let mut row = Vec::new();
+            let mut chars = line.chars().peekable();
+            while chars.peek().is_some() {
+                let mut count = 0usize;
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    count = count
+                        .checked_mul(10)?
+                        .checked_add(digit as usize - '0' as usize)?;
+                }
+                if chars.next()? != '*' {
+                    return None;
+                }
+                let character = chars.next()?;
+                let mut combining = String::new();
+                while let Some(mark) = chars.next_if(|&c| char_width(c) == 0) {
+                    combining.push(mark);
+                }
+                let mut attrs = Attributes::default();
+                if chars.next_if_eq(&'[').is_some() {
+                    let params: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                    attrs = attrs_from_sgr(&params)?;
+                }
+
+                for _ in 0..count {
+                    row.push(Cell {
+                        character,
+                        attrs: attrs.clone(),
+                        continuation: false,
+                        link: None,
+                        combining: combining.clone(),
+                    });
+                    if char_width(character) == 2 {
+                        row.push(Cell {
+                            character: ' ',
+                            attrs: attrs.clone(),
+                            continuation: true,
+                            link: None,
+                            combining: String::new(),
+                        });
+                    }
+                }
+            }
+            rows.push(row);
+        }
+
+        let cols = rows[0].len();
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        let mut terminal = Terminal::new(cols, rows.len());
+        terminal.grid = rows;
+        Some(terminal)
+    }
+
+    // Plain linearization for screen readers: no padding, no trailing blank
+    // lines, and every run of blank cells read as one space. Tab gaps are not
+    // told apart from typed spaces, so aligned columns collapse too.
+    pub fn accessible_text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .grid
+            .iter()
+            .map(|row| {
+                let mut line = String::new();
+                for cell in row.iter().filter(|cell| !cell.continuation) {
+                    if cell.character != ' ' || !cell.combining.is_empty() || !line.ends_with(' ') {
+                        cell.push_text(&mut line);
+                    }
+                }
+                line.trim_end().to_string()
+            })
+            .collect();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(cols: usize, rows: usize, input: &[u8]) -> Terminal {
+        let mut terminal = Terminal::new(cols, rows);
+        terminal.feed(input);
+        terminal
+    }
+
+    fn position(terminal: &Terminal) -> (usize, usize) {
+        (terminal.cursor().row, terminal.cursor().col)
+    }
+
+    fn line(terminal: &Terminal, row: usize) -> String {
+        terminal
+            .render_content()
+            .lines()
+            .nth(row)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string()
+    }
+
+    fn rect(top: u16, left: u16, bottom: u16, right: u16) -> Rect {
+        Rect {
+            top,
+            left,
+            bottom,
+            right,
+        }
+    }
+
+    #[test]
+    fn mode_1049_restores_the_cursor_and_primary_screen() {
+        let t = terminal(10, 5, b"hi\x1b[3;4H\x1b[?1049h\x1b[1;1Hx\x1b[?1049l");
+        assert_eq!(position(&t), (2, 3));
+        assert_eq!(line(&t, 0), "hi");
+    }
+
+    #[test]
+    fn mode_1049_enters_a_blank_alt_screen() {
+        let t = terminal(10, 5, b"hi\x1b[?1049h");
+        assert_eq!(line(&t, 0), "");
+    }
+
+    #[test]
+    fn mode_1047_keeps_the_cursor_where_the_alt_screen_left_it() {
+        let t = terminal(10, 5, b"hi\x1b[3;4H\x1b[?1047h\x1b[1;1Hx\x1b[?1047l");
+        assert_eq!(position(&t), (0, 1));
+        assert_eq!(line(&t, 0), "hi");
+    }
+
+    #[test]
+    fn mode_47_switches_buffers_without_touching_the_cursor() {
+        let t = terminal(10, 5, b"hi\x1b[?47hx\x1b[?47l");
+        assert_eq!(position(&t), (0, 3));
+        assert_eq!(line(&t, 0), "hi");
+    }
+
+    #[test]
+    fn accessible_text_collapses_blank_runs_to_one_space() {
+        let t = terminal(20, 5, b"a\tb\r\nhi   there\r\n");
+        assert_eq!(t.accessible_text(), "a b\nhi there");
+    }
+
+    #[test]
+    fn accessible_text_skips_wide_char_continuations_and_trailing_blanks() {
+        let t = terminal(10, 4, "中文 x  \r\n\r\n".as_bytes());
+        assert_eq!(t.accessible_text(), "中文 x");
+    }
+
+    #[test]
+    fn alignment_test_fills_the_screen_with_e_and_homes_the_cursor() {
+        let mut t = terminal(4, 3, b"ab\x1b[3;3H");
+        t.process_event(&TerminalEvent::AlignmentTest);
+        for row in 0..3 {
+            for col in 0..4 {
+                assert_eq!(t.cell(row, col).unwrap().character(), 'E');
+            }
+        }
+        assert_eq!(position(&t), (0, 0));
+    }
+
+    #[test]
+    fn esc_hash_8_decodes_to_the_alignment_test() {
+        let t = terminal(2, 1, b"\x1b#8");
+        assert_eq!(t.render_content(), "EE\n");
+    }
+
+    #[test]
+    fn double_width_is_recorded_for_the_cursor_row() {
+        let t = terminal(4, 2, b"ab\x1b#6");
+        assert_eq!(t.line_attribute(0), LineAttribute::DoubleWidth);
+        assert_eq!(t.line_attribute(1), LineAttribute::SingleWidth);
+    }
+
+    #[test]
+    fn double_height_halves_decode_to_their_line_attributes() {
+        let t = terminal(4, 3, b"\x1b#3\n\x1b#4\n\x1b#6\x1b#5");
+        assert_eq!(t.line_attribute(0), LineAttribute::DoubleHeightTop);
+        assert_eq!(t.line_attribute(1), LineAttribute::DoubleHeightBottom);
+        assert_eq!(t.line_attribute(2), LineAttribute::SingleWidth);
+    }
+
+    #[test]
+    fn double_width_rows_render_only_half_the_columns() {
+        let t = terminal(4, 2, b"abc\x1b#6");
+        assert_eq!(t.render_content(), "ab\n    \n");
+    }
+
+    #[test]
+    fn line_attributes_stay_with_their_screen_buffer() {
+        let mut t = terminal(10, 2, b"\x1b#6\x1b[?1049h0123456789");
+        assert_eq!(t.line_attribute(0), LineAttribute::SingleWidth);
+        assert_eq!(line(&t, 0), "0123456789");
+        t.feed(b"\x1b[?1049l");
+        assert_eq!(t.line_attribute(0), LineAttribute::DoubleWidth);
+    }
+
+    #[test]
+    fn mode_registry_tracks_arbitrary_modes() {
+        let t = terminal(4, 2, b"\x1b[?2004h\x1b[?1006h\x1b[4h\x1b[?1006l");
+        assert!(t.mode_enabled(true, 2004));
+        assert!(!t.mode_enabled(true, 1006));
+        assert!(t.mode_enabled(false, 4));
+        assert!(!t.mode_enabled(true, 4));
+    }
+
+    #[test]
+    fn mode_registry_starts_with_power_on_defaults() {
+        let t = Terminal::new(4, 2);
+        assert!(t.mode_enabled(true, 7));
+        assert!(t.mode_enabled(true, 25));
+        assert!(!t.mode_enabled(true, 2004));
+    }
+
+    fn text(rows: &[Vec<Cell>]) -> Vec<String> {
+        rows.iter()
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.character).collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn viewport_straddles_scrollback_and_screen() {
+        let t = terminal(4, 2, b"a\r\nb\r\nc\r\nd");
+        assert_eq!(t.scrollback.len(), 2);
+        assert_eq!(text(&t.render_viewport(1, 2)), ["b", "c"]);
+        assert_eq!(text(&t.render_viewport(0, 1)), ["a"]);
+    }
+
+    #[test]
+    fn viewport_clamps_to_existing_lines() {
+        let t = terminal(4, 2, b"a\r\nb\r\nc\r\nd");
+        assert_eq!(text(&t.render_viewport(9, 3)), ["b", "c", "d"]);
+        assert_eq!(t.render_viewport(0, 99).len(), 4);
+    }
+
+    #[test]
+    fn osc_22_sets_pointer_shape() {
+        assert_eq!(Terminal::new(4, 2).pointer_shape(), None);
+        let mut t = terminal(4, 2, b"\x1b]22;pointer\x07");
+        assert_eq!(t.pointer_shape(), Some("pointer"));
+        t.feed(b"\x1b]22;text\x1b\\");
+        assert_eq!(t.pointer_shape(), Some("text"));
+    }
+
+    fn apply(batch: bool, input: &[u8]) -> (Terminal, usize) {
+        let mut parser = Parser::new();
+        parser.set_batch_prints(batch);
+        parser.advance(&mut vte::Parser::new(), input);
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_events(&parser.events);
+        (terminal, parser.events.len())
+    }
+
+    #[test]
+    fn coalesced_prints_match_per_char_prints() {
+        let input = "hello \x1b[31mred\x1b[0m world wraps\r\n\x1b[1mbold 中文 text\x1b[4h!\x1b[2GX"
+            .as_bytes();
+        let (single, single_events) = apply(false, input);
+        let (batched, batched_events) = apply(true, input);
+        assert!(batched_events < single_events);
+        assert_eq!(single.render_content(), batched.render_content());
+        assert_eq!(position(&single), position(&batched));
+        for (a, b) in single.grid.iter().zip(&batched.grid) {
+            for (x, y) in a.iter().zip(b) {
+                assert_eq!((x.character, &x.attrs), (y.character, &y.attrs));
+            }
+        }
+    }
+
+    #[test]
+    fn mutations_bump_generation_and_reads_do_not() {
+        let mut t = Terminal::new(4, 2);
+        let start = t.generation();
+        t.feed(b"a");
+        let printed = t.generation();
+        assert!(printed > start);
+
+        let _ = t.render_content();
+        let _ = t.accessible_text();
+        let _ = t.cursor();
+        assert_eq!(t.generation(), printed);
+
+        t.feed(b"\x1b[2J");
+        let erased = t.generation();
+        assert!(erased > printed);
+        t.feed(b"\x1b[2;3H");
+        assert!(t.generation() > erased);
+    }
+
+    #[test]
+    fn insert_and_delete_char_stay_inside_left_right_margins() {
+        let mut t = terminal(10, 3, b"0123456789\x1b[?69h\x1b[3;6s\x1b[1;4H\x1b[2@");
+        assert_eq!(line(&t, 0), "012  36789");
+        t.feed(b"\x1b[1;5H\x1b[P");
+        assert_eq!(line(&t, 0), "012 3 6789");
+    }
+
+    #[test]
+    fn wrap_returns_to_left_margin() {
+        let t = terminal(10, 3, b"\x1b[?69h\x1b[3;6s\x1b[1;3Habcdef");
+        assert_eq!(line(&t, 0), "  abcd");
+        assert_eq!(line(&t, 1), "  ef");
+    }
+
+    #[test]
+    fn line_feed_scrolls_only_between_left_and_right_margins() {
+        let mut t = terminal(6, 3, b"abcdef\r\nghijkl\r\nmnopqr");
+        t.feed(b"\x1b[?69h\x1b[2;4s\x1b[3;2H\n");
+        assert_eq!(line(&t, 0), "ahijef");
+        assert_eq!(line(&t, 1), "gnopkl");
+        assert_eq!(line(&t, 2), "m   qr");
+        assert!(t.scrollback.is_empty());
+
+        // Full-width margins scroll whole rows as usual
+        t.feed(b"\x1b[?69l\x1b[3;1H\n");
+        assert_eq!(line(&t, 0), "gnopkl");
+        assert_eq!(t.scrollback.len(), 1);
+    }
+
+    #[test]
+    fn csi_s_saves_cursor_without_declrmm() {
+        let t = terminal(10, 3, b"\x1b[2;3H\x1b[s\x1b[H\x1b[u");
+        assert_eq!(position(&t), (1, 2));
+    }
+
+    #[test]
+    fn export_cells_resolves_colors_and_flags() {
+        let t = terminal(4, 2, "\x1b[1;4;31;44mx\x1b[0my中".as_bytes());
+        let cells = t.export_cells();
+        assert_eq!(cells.len(), 8);
+        assert_eq!(
+            cells[0],
+            CellExport {
+                character: 'x' as u32,
+                fg: [205, 0, 0],
+                bg: [0, 0, 238],
+                flags: CellExport::BOLD | CellExport::UNDERLINE,
+            }
+        );
+        assert_eq!(cells[1].fg, [229, 229, 229]);
+        assert_eq!(cells[1].flags, 0);
+        assert_eq!(cells[2].character, '中' as u32);
+        assert_eq!(cells[3].flags, CellExport::CONTINUATION);
+    }
+
+    #[test]
+    fn default_palette_covers_cube_and_grays() {
+        let palette = default_palette();
+        assert_eq!(palette[196], (255, 0, 0));
+        assert_eq!(palette[16 + 36 + 6 * 2 + 3], (95, 135, 175));
+        assert_eq!(palette[255], (238, 238, 238));
+    }
+
+    #[test]
+    fn cursor_report_is_region_relative_in_origin_mode() {
+        let mut t = terminal(10, 10, b"\x1b[3;8r\x1b[?6h\x1b[2;4H\x1b[6n");
+        assert_eq!(t.take_responses(), b"\x1b[2;4R");
+        assert_eq!(position(&t), (3, 3));
+        t.feed(b"\x1b[?6l\x1b[6n");
+        assert_eq!(t.take_responses(), b"\x1b[1;1R");
+    }
+
+    #[test]
+    fn linefeed_scrolls_only_at_bottom_margin() {
+        let mut t = terminal(10, 10, b"\x1b[3;8r\x1b[8;1Ha\nb\nc");
+        assert_eq!(t.scrollback.len(), 0);
+        assert_eq!(position(&t), (7, 1));
+        t.feed(b"\x1b[10;1Hz\n");
+        assert_eq!(position(&t), (9, 0));
+    }
+
+    #[test]
+    fn absolute_lines_round_trip_with_scrollback() {
+        let t = terminal(4, 2, b"a\r\nb\r\nc\r\nd");
+        assert_eq!(t.absolute_line(0), 2);
+        assert_eq!(t.viewport_row_of(3), Some(1));
+        assert_eq!(t.viewport_row_of(1), None);
+        assert_eq!(t.viewport_row_of(4), None);
+        for row in 0..2 {
+            assert_eq!(t.viewport_row_of(t.absolute_line(row)), Some(row));
+        }
+    }
+
+    fn recording() -> Vec<(Duration, Vec<u8>)> {
+        vec![
+            (Duration::from_millis(0), b"ab\x1b[".to_vec()),
+            (Duration::from_millis(30), b"31mc\r\nd".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn replay_reassembles_split_sequences() {
+        let mut t = Terminal::new(6, 2);
+        t.replay(recording().into_iter());
+        assert_eq!(t.render_content(), "abc   \nd     \n");
+        assert_eq!(t.grid[0][2].attrs.foreground, Color::Red);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn realtime_replay_honours_offsets() {
+        let start = std::time::Instant::now();
+        let mut t = Terminal::new(6, 2);
+        t.replay_realtime(recording().into_iter());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(t.render_content(), "abc   \nd     \n");
+    }
+
+    #[test]
+    fn put_cell_writes_in_bounds_without_moving_cursor() {
+        let mut t = Terminal::new(3, 2);
+        assert!(!t.put_cell(2, 0, 'x', Attributes::default()));
+        assert!(!t.put_cell(0, 3, 'x', Attributes::default()));
+        assert!(t.put_cell(1, 2, 'x', Attributes::default()));
+        assert_eq!(line(&t, 1), "  x");
+        assert_eq!(position(&t), (0, 0));
+    }
+
+    #[test]
+    fn put_cell_breaks_wide_pairs() {
+        let mut t = terminal(4, 1, "中中".as_bytes());
+        assert!(t.put_cell(0, 1, 'x', Attributes::default()));
+        assert_eq!(line(&t, 0), " x中");
+        assert!(!t.cell(0, 1).unwrap().continuation);
+        assert!(t.put_cell(0, 2, 'y', Attributes::default()));
+        assert_eq!(line(&t, 0), " xy");
+        assert!(t.grid[0].iter().all(|cell| !cell.continuation));
+    }
+
+    #[test]
+    fn erase_and_fill_rect_break_wide_pairs_at_their_edges() {
+        let mut t = terminal(6, 2, "中中中".as_bytes());
+        let generation = t.generation();
+        t.fill_rect('x', &Attributes::default(), rect(1, 2, 1, 3));
+        assert_eq!(t.generation(), generation + 1);
+        assert_eq!(line(&t, 0), " xx 中");
+
+        let mut t = terminal(6, 2, "中中中".as_bytes());
+        t.erase_rect(rect(1, 4, 1, 4));
+        assert_eq!(line(&t, 0), "中  中");
+        assert!(!t.cell(0, 3).unwrap().continuation);
+    }
+
+    #[test]
+    fn copy_rect_drops_wide_halves_cut_by_the_source() {
+        // The source cuts through both outer glyphs; only the middle one is whole
+        let mut t = terminal(6, 2, "中中中".as_bytes());
+        t.copy_rect(rect(1, 2, 1, 5), 2, 1);
+        assert_eq!(line(&t, 1), " 中");
+        assert!(
+            t.grid[1]
+                .iter()
+                .skip(3)
+                .all(|cell| *cell == Cell::default())
+        );
+    }
+
+    #[test]
+    fn decscusr_sets_style_and_blink() {
+        let mut t = Terminal::new(3, 2);
+        assert_eq!(t.cursor_style(), 0);
+        assert!(t.cursor_blinks());
+        t.feed(b"\x1b[1 q");
+        assert!(t.cursor_blinks());
+        t.feed(b"\x1b[2 q");
+        assert_eq!(t.cursor_style(), 2);
+        assert!(!t.cursor_blinks());
+        t.feed(b"\x1b[5 q");
+        assert!(t.cursor_blinks());
+        t.feed(b"\x1b[6 q");
+        assert!(!t.cursor_blinks());
+    }
+
+    #[test]
+    fn insert_mode_shifts_text_right() {
+        let mut t = terminal(6, 2, b"abcdef\x1b[1;3H\x1b[4hXY");
+        assert!(t.mode_enabled(false, 4));
+        assert_eq!(line(&t, 0), "abXYcd");
+        t.feed(b"\x1b[4lZ");
+        assert!(!t.mode_enabled(false, 4));
+        assert_eq!(line(&t, 0), "abXYZd");
+    }
+
+    #[test]
+    fn str_width_counts_wide_and_combining_characters() {
+        assert_eq!(str_width("hello"), 5);
+        assert_eq!(str_width("中文字"), 6);
+        assert_eq!(str_width("e\u{301}a\u{308}"), 2);
+        assert_eq!(str_width("🚀"), 2);
+        assert_eq!(str_width("☀✂"), 2);
+        assert_eq!(str_width("☕⚡✅❌"), 8);
+        assert_eq!(char_width('✓'), 1);
+        assert_eq!(char_width('❯'), 1);
+        assert_eq!(str_width("★✔"), 2);
+        assert_eq!(str_width("🫠"), 2);
+    }
+
+    #[test]
+    fn wide_characters_advance_two_columns_and_wrap_whole() {
+        let t = terminal(5, 2, "ab中文".as_bytes());
+        assert_eq!(t.render_content(), "ab中 \n文   \n");
+        assert!(t.grid[0][3].continuation);
+        assert_eq!(position(&t), (1, 2));
+        assert_eq!(t.accessible_text(), "ab中\n文");
+    }
+
+    #[test]
+    fn combining_marks_stay_on_the_preceding_cell() {
+        let t = terminal(4, 2, "e\u{301}x中\u{308}".as_bytes());
+        assert_eq!(t.grid[0][0].combining(), "\u{301}");
+        assert_eq!(t.grid[0][2].combining(), "\u{308}");
+        assert_eq!(position(&t), (0, 3));
+        assert_eq!(line(&t, 0), "e\u{301}x中\u{308}");
+        assert_eq!(t.accessible_text(), "e\u{301}x中\u{308}");
+    }
+
+    #[test]
+    fn combining_mark_joins_last_column_while_wrap_is_pending() {
+        let t = terminal(2, 2, "abc\u{301}".as_bytes());
+        assert_eq!(line(&t, 0), "ab");
+        assert_eq!(line(&t, 1), "c\u{301}");
+        let t = terminal(2, 2, "ab\u{301}".as_bytes());
+        assert_eq!(line(&t, 0), "ab\u{301}");
+        assert!(
+            terminal(2, 2, "\u{301}".as_bytes()).grid[0][0]
+                .combining()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn xtwinops_reports_text_and_pixel_size() {
+        let mut t = terminal(80, 24, b"\x1b[18t");
+        assert_eq!(t.take_responses(), b"\x1b[8;24;80t");
+        t.set_cell_pixel_size(10, 20);
+        t.feed(b"\x1b[14t\x1b[22;0t");
+        assert_eq!(t.take_responses(), b"\x1b[4;480;800t");
+    }
+
+    #[test]
+    fn responses_can_be_peeked_and_cleared() {
+        let mut t = terminal(10, 3, b"\x1b[6n");
+        assert_eq!(t.peek_responses(), b"\x1b[1;1R");
+        assert_eq!(t.peek_responses(), b"\x1b[1;1R");
+        t.clear_responses();
+        assert!(t.peek_responses().is_empty());
+        t.feed(b"\x1b[6n");
+        assert_eq!(t.take_responses(), b"\x1b[1;1R");
+        assert!(t.peek_responses().is_empty());
+    }
+
+    #[test]
+    fn segments_group_runs_by_attributes() {
+        let t = terminal(6, 2, "\x1b[31mab中\x1b[0mcd".as_bytes());
+        let segments = t.segments();
+        assert_eq!(segments[0].len(), 2);
+        assert_eq!(segments[0][0].text, "ab中");
+        assert_eq!(segments[0][0].attrs.foreground, Color::Red);
+        assert_eq!(segments[0][1].text, "cd");
+        assert!(segments[0][1].attrs.is_default());
+        assert_eq!(segments[1].len(), 1);
+    }
+
+    #[test]
+    fn soft_reset_restores_power_on_modes() {
+        let mut t = Terminal::new(4, 3);
+        assert!(t.autowrap() && t.cursor_visible() && !t.origin_mode());
+        t.feed(b"\x1b[?7l\x1b[?6h\x1b[?25l\x1b[2;3r\x1b[1mabcdef");
+        assert!(!t.autowrap() && t.origin_mode() && !t.cursor_visible());
+        assert_eq!(line(&t, 1), "abcf");
+
+        t.feed(b"\x1b[!pX");
+        assert!(t.autowrap() && !t.origin_mode() && t.cursor_visible());
+        assert_eq!(t.bottom_margin, 2);
+        assert_eq!(t.grid[1][3].character, 'X');
+        assert!(!t.grid[1][3].attrs.bold);
+    }
+
+    #[test]
+    fn erase_below_includes_the_cursor_cell() {
+        let t = terminal(3, 3, b"abcdefgh\x1b[2;2H\x1b[0J");
+        assert_eq!(t.render_content(), "abc\nd  \n   \n");
+        assert_eq!(t.grid[1][0].character, 'd');
+        assert_eq!(t.grid[1][1].character, ' ');
+    }
+
+    #[test]
+    fn erase_above_includes_the_cursor_cell() {
+        let t = terminal(3, 3, b"abcdefgh\x1b[2;2H\x1b[1J");
+        assert_eq!(t.render_content(), "   \n  f\ngh \n");
+        assert_eq!(t.grid[1][1].character, ' ');
+        assert_eq!(t.grid[1][2].character, 'f');
+    }
+
+    #[test]
+    fn tbc_clears_one_or_all_tab_stops() {
+        let mut t = terminal(20, 2, b"\tA");
+        assert_eq!(position(&t).1, 9);
+        t.feed(b"\r\x1b[3C\x1bH\r\tB");
+        assert_eq!(position(&t).1, 4);
+        t.feed(b"\x1b[3g\r\t");
+        assert_eq!(position(&t).1, 19);
+
+        let mut t = terminal(20, 2, b"\x1b[1;9H\x1b[0g\r\t");
+        assert_eq!(position(&t).1, 16);
+        t.clear_tab_stops(3);
+        t.feed(b"\r\t");
+        assert_eq!(position(&t).1, 19);
+    }
+
+    // One test so the two cases cannot race on the shared environment
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_env_reads_size_or_falls_back() {
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::set_var("COLUMNS", "100");
+            std::env::set_var("LINES", "30");
+        }
+        assert_eq!(Terminal::from_env().size(), (100, 30));
+
+        unsafe {
+            std::env::set_var("COLUMNS", "x");
+            std::env::remove_var("LINES");
+        }
+        assert_eq!(Terminal::from_env().size(), (80, 24));
+    }
+
+    #[test]
+    fn cursor_report_at_bottom_right_of_large_screen() {
+        let mut t = terminal(200, 50, b"\x1b[999;999H\x1b[6n");
+        assert_eq!(t.take_responses(), b"\x1b[50;200R");
+    }
+
+    struct CountingRenderer {
+        cells: usize,
+        lines: usize,
+    }
+
+    impl Renderer for CountingRenderer {
+        type Output = (usize, usize);
+
+        fn cell(&mut self, _cell: &Cell) {
+            self.cells += 1;
+        }
+
+        fn end_line(&mut self, _row: usize) {
+            self.lines += 1;
+        }
+
+        fn finish(self) -> Self::Output {
+            (self.cells, self.lines)
+        }
+    }
+
+    #[test]
+    fn custom_renderer_sees_every_cell() {
+        let t = terminal(4, 2, "a中b".as_bytes());
+        let counter = CountingRenderer { cells: 0, lines: 0 };
+        assert_eq!(t.render_with(counter), (8, 2));
+    }
+
+    #[test]
+    fn builtin_renderers_share_the_trait() {
+        let t = terminal(4, 2, b"a\x1b[1;31mb<\x1b[0mc");
+        assert_eq!(t.render_content(), "ab<c\n    \n");
+        assert_eq!(t.render_to_ansi(), "a\x1b[1;31mb<\x1b[0mc\n    \n");
+        assert_eq!(
+            t.render_to_html(),
+            "<pre>a<span style=\"color:#cd0000;background-color:#000000;font-weight:bold\">b&lt;</span>c\n    \n</pre>"
+        );
+    }
+
+    #[test]
+    fn nul_and_del_never_reach_the_grid() {
+        let mut t = Terminal::new(4, 2);
+        t.process_event(&TerminalEvent::Print {
+            char: '\0',
+            attrs: Attributes::default(),
+        });
+        t.process_event(&TerminalEvent::PrintStr {
+            text: "\x7fa".into(),
+            attrs: Attributes::default(),
+        });
+        assert_eq!(position(&t), (0, 1));
+        t.feed(b"\x00\x7fb");
+        assert_eq!(position(&t), (0, 2));
+        assert_eq!(t.render_content(), "ab  \n    \n");
+    }
+
+    #[test]
+    fn logical_line_joins_wrapped_rows() {
+        let mut t = terminal(4, 5, b"x\r\nabcdefghij\r\ny");
+        for row in 1..=3 {
+            assert_eq!(t.logical_line(row), "abcdefghij");
+        }
+        assert_eq!(t.logical_line(0), "x");
+        assert_eq!(t.logical_line(4), "y");
+        t.feed(b"\x1b[2;1H\x1b[2K");
+        assert_eq!(t.logical_line(2), "efghij");
+    }
+
+    #[test]
+    fn wrap_flags_belong_to_their_screen_buffer() {
+        let mut t = terminal(4, 3, b"abcdef");
+        t.feed(b"\x1b[?47h\x1b[1;1H12\x1b[2;1H34");
+        assert_eq!(t.logical_line(0), "12");
+        t.feed(b"\x1b[?47l");
+        assert_eq!(t.logical_line(1), "abcdef");
+    }
+
+    #[test]
+    fn origin_mode_homes_inside_the_margin_box() {
+        let mut t = terminal(20, 10, b"\x1b[?69h\x1b[5;15s\x1b[3;8r\x1b[?6h");
+        assert_eq!(position(&t), (2, 4));
+        t.feed(b"\x1b[99;99H");
+        assert_eq!(position(&t), (7, 14));
+        t.feed(b"\x1b[3G");
+        assert_eq!(position(&t).1, 6);
+        t.feed(b"\x1b[6n");
+        assert_eq!(t.take_responses(), b"\x1b[6;3R");
+        t.feed(b"\x1b[?6l\x1b[3G");
+        assert_eq!(position(&t).1, 2);
+    }
+
+    #[test]
+    fn rle_round_trips_a_sparse_grid() {
+        let t = terminal(
+            6,
+            3,
+            "\x1b[2;2H\x1b[1;38;5;200mab*\x1b[0m中\x1b[3;1H[]".as_bytes(),
+        );
+        let rle = t.to_rle();
+        assert_eq!(
+            rle,
+            "6* \n1* 1*a[1;38;5;200]1*b[1;38;5;200]1**[1;38;5;200]1*中\n1*[1*]4* "
+        );
+        let restored = Terminal::from_rle(&rle).unwrap();
+        assert_eq!(restored.size(), (6, 3));
+        assert_eq!(restored.to_rle(), rle);
+        assert_eq!(restored.render_to_ansi(), t.render_to_ansi());
+    }
+
+    #[test]
+    fn rle_round_trips_wide_and_combining_cells() {
+        let t = terminal(6, 2, "\x1b[4m中\x1b[0mee\u{301}\u{302}x".as_bytes());
+        let rle = t.to_rle();
+        assert!(rle.starts_with("1*中[4]1*e1*e\u{301}\u{302}1*x"), "{rle:?}");
+        let restored = Terminal::from_rle(&rle).unwrap();
+        assert_eq!(restored.grid, t.grid);
+    }
+
+    #[test]
+    fn from_rle_rejects_malformed_input() {
+        assert!(Terminal::from_rle("2*a\n1*b").is_none());
+        assert!(Terminal::from_rle("x").is_none());
+    }
+
+    #[test]
+    fn focus_reports_follow_mode_1004() {
+        let mut t = Terminal::new(6, 3);
+        assert_eq!(t.focus_event(true), None);
+        t.feed(b"\x1b[?1004h");
+        assert_eq!(t.focus_event(true), Some(b"\x1b[I".to_vec()));
+        assert_eq!(t.focus_event(false), Some(b"\x1b[O".to_vec()));
+        t.feed(b"\x1b[?1004l");
+        assert_eq!(t.focus_event(false), None);
+    }
+
+    #[test]
+    fn apply_events_returns_only_its_own_responses() {
+        let mut t = terminal(6, 3, b"\x1b[5nab");
+        let replies = t.apply_events(&[
+            TerminalEvent::DeviceStatusReport(6),
+            TerminalEvent::DeviceAttributes,
+        ]);
+        assert_eq!(replies, b"\x1b[1;3R\x1b[?62;22c");
+        assert_eq!(t.take_responses(), b"\x1b[0n");
+    }
+
+    #[test]
+    fn utf8_split_across_feeds_prints_one_character() {
+        let mut t = Terminal::new(6, 3);
+        t.feed(b"a\xe4");
+        t.feed(b"\xb8");
+        t.feed(b"\xadb");
+        assert_eq!(line(&t, 0), "a中b");
+        assert_eq!(t.grid[0][1].character, '中');
+        assert!(t.grid[0][2].continuation);
+        assert_eq!(position(&t), (0, 4));
+    }
+
+    #[test]
+    fn decfra_and_decera_touch_only_their_rectangles() {
+        let mut t = terminal(
+            5,
+            4,
+            b"abcdefghijklmno\x1b[1;31m\x1b[35;2;2;3;3$x\x1b[0m\x1b[1;4;2;99$z",
+        );
+        assert_eq!(t.render_content(), "abc  \nf##  \nk##no\n     \n");
+        assert_eq!(t.grid[1][1].attrs.foreground, Color::Red);
+        // Inverted corners are ignored
+        t.feed(b"\x1b[1;1;3;1$x\x1b[4;4;2;2$z");
+        assert_eq!(t.render_content(), "abc  \nf##  \nk##no\n     \n");
+    }
+
+    #[test]
+    fn rect_methods_clamp_to_the_grid() {
+        let mut t = terminal(3, 2, b"abcdef");
+        t.fill_rect('*', &Attributes::default(), rect(2, 2, 9, 9));
+        assert_eq!(t.render_content(), "abc\nd**\n");
+        t.erase_rect(rect(1, 3, 0, 0));
+        assert_eq!(t.render_content(), "ab \nd* \n");
+        t.fill_rect('中', &Attributes::default(), rect(1, 1, 1, 1));
+        assert_eq!(line(&t, 0), "ab");
+    }
+
+    #[test]
+    fn deccra_copies_without_overlap() {
+        let t = terminal(5, 4, b"abcdefghijklmno\x1b[1;1;2;2;1;3;4;1$v");
+        assert_eq!(t.render_content(), "abcde\nfghij\nklmab\n   fg\n");
+    }
+
+    #[test]
+    fn deccra_handles_overlap_in_both_directions() {
+        let t = terminal(5, 4, b"abcdefghijklmno\x1b[1;1;2;4;1;2;2;1$v");
+        assert_eq!(t.render_content(), "abcde\nfabcd\nkfghi\n     \n");
+        let t = terminal(5, 4, b"abcdefghijklmno\x1b[2;2;3;5;1;1;1;1$v");
+        assert_eq!(t.render_content(), "ghije\nlmnoj\nklmno\n     \n");
+    }
+
+    #[test]
+    fn copy_rect_drops_cells_past_the_edge() {
+        let mut t = terminal(3, 2, b"abcdef");
+        t.copy_rect(rect(1, 1, 1, 3), 2, 2);
+        assert_eq!(t.render_content(), "abc\ndab\n");
+    }
+
+    #[test]
+    fn ansi_output_turns_off_bold_without_a_reset() {
+        let t = terminal(4, 1, b"\x1b[1;31ma\x1b[22mb\x1b[0mc");
+        assert_eq!(t.render_to_ansi(), "\x1b[1;31ma\x1b[22mb\x1b[0mc \n");
+    }
+
+    #[test]
+    fn decrqss_reports_the_current_sgr() {
+        let mut t = terminal(4, 1, b"\x1b[1;31m\x1bP$qm\x1b\\");
+        assert_eq!(t.take_responses(), b"\x1bP1$r0;1;31m\x1b\\");
+        t.feed(b"\x1bP$qzz\x1b\\");
+        assert_eq!(t.take_responses(), b"\x1bP0$r\x1b\\");
+    }
+
+    #[test]
+    fn damage_is_one_rect_per_changed_row() {
+        let mut t = terminal(10, 4, b"\x1b[2;3Hx\x1b[2;7Hy\x1b[4;5Hz");
+        assert_eq!(t.take_damage(), [rect(2, 3, 2, 7), rect(4, 5, 4, 5)]);
+        assert!(t.take_damage().is_empty());
+        // Rewriting a cell with the same content is not damage
+        t.feed(b"\x1b[2;3Hx");
+        assert!(t.take_damage().is_empty());
+    }
+
+    #[test]
+    fn feed_str_applies_embedded_sequences() {
+        let mut t = Terminal::new(4, 1);
+        t.feed_str("a\x1b[1mb");
+        assert_eq!(t.render_content(), "ab  \n");
+        assert!(!t.grid[0][0].attrs.bold);
+        assert!(t.grid[0][1].attrs.bold);
+    }
+
+    #[test]
+    fn latest_mouse_tracking_level_wins() {
+        let mut t = Terminal::new(4, 1);
+        assert_eq!(t.mouse_tracking(), MouseTracking::Off);
+        t.feed(b"\x1b[?1002h\x1b[?1000h");
+        assert_eq!(t.mouse_tracking(), MouseTracking::Click);
+        assert!(t.mode_enabled(true, 1000));
+        assert!(!t.mode_enabled(true, 1002));
+        t.feed(b"\x1b[?1003h");
+        assert_eq!(t.mouse_tracking(), MouseTracking::AnyEvent);
+        t.feed(b"\x1b[?1003l");
+        assert_eq!(t.mouse_tracking(), MouseTracking::Off);
+        assert!(!t.mode_enabled(true, 1003));
+    }
+
+    #[test]
+    fn attrs_at_reads_cells_with_bounds_checks() {
+        let t = terminal(4, 2, b"a\x1b[32mb");
+        assert_eq!(t.attrs_at(0, 1).unwrap().foreground, Color::Green);
+        assert_eq!(t.attrs_at(0, 0), Some(&Attributes::default()));
+        assert!(t.attrs_at(2, 0).is_none());
+        assert!(t.attrs_at(0, 4).is_none());
+    }
+
+    #[test]
+    fn rep_repeats_wide_characters_two_cells_each() {
+        let t = terminal(7, 3, "x中\x1b[3b".as_bytes());
+        assert_eq!(t.render_content(), "x中中中\n中     \n       \n");
+        for col in [1, 3, 5] {
+            assert_eq!(t.grid[0][col].character, '中');
+            assert!(t.grid[0][col + 1].continuation);
+        }
+        assert!(t.grid[1][1].continuation);
+        assert_eq!(position(&t), (1, 2));
+    }
+
+    #[test]
+    fn rep_without_count_repeats_once() {
+        let t = terminal(5, 1, b"a\x1b[b");
+        assert_eq!(t.render_content(), "aa   \n");
+    }
+
+    #[test]
+    fn resizes_are_capped_at_the_maximum_grid_size() {
+        let mut t = Terminal::new(10, 5);
+        assert_eq!((t.max_cols, t.max_rows), (10_000, 10_000));
+        t.set_max_size(50, 20);
+        t.feed(b"\x1b[8;2000;60000t");
+        assert_eq!(t.size(), (50, 20));
+        t.resize(100, 100);
+        assert_eq!(t.size(), (50, 20));
+        assert_eq!(t.grid.len(), 20);
+        assert_eq!(t.tab_stops.len(), 50);
+        assert_eq!(t.inactive_grid[0].len(), 50);
+
+        t.feed(b"\x1b[8;0;30t\x1b[99;99Hx\x1b[18t");
+        assert_eq!(t.size(), (30, 20));
+        assert_eq!(t.take_responses(), b"\x1b[8;20;30t");
+    }
+
+    #[test]
+    fn screen_hash_ignores_scrollback() {
+        let mut a = terminal(4, 2, b"old\r\nver\r\nabc\x1b[2J\x1b[Hhi");
+        let b = terminal(4, 2, b"hi");
+        assert!(!a.scrollback.is_empty());
+        assert_eq!(a.screen_hash(), b.screen_hash());
+        a.feed(b"\x1b[31m\x1b[1;1Hh");
+        assert_ne!(a.screen_hash(), b.screen_hash());
+        assert_ne!(b.screen_hash(), terminal(4, 2, b"hj").screen_hash());
+    }
+
+    #[test]
+    fn screen_hash_ignores_link_history() {
+        let linked = b"\x1b]8;;http://x.test\x07hi\x1b]8;;\x07";
+        let mut a = terminal(4, 2, b"\x1b]8;;http://old\x07\x1b]8;;\x07");
+        a.feed(linked);
+        let b = terminal(4, 2, linked);
+        assert_ne!(a.cell(0, 0).unwrap().link, b.cell(0, 0).unwrap().link);
+        assert_eq!(a.screen_hash(), b.screen_hash());
+        assert_ne!(a.screen_hash(), terminal(4, 2, b"hi").screen_hash());
+        assert_ne!(
+            a.screen_hash(),
+            terminal(4, 2, b"\x1b]8;;http://y.test\x07hi").screen_hash()
+        );
+    }
+
+    #[test]
+    fn secondary_device_attributes_reply_is_configurable() {
+        let mut t = terminal(4, 1, b"\x1b[>c\x1b[>0c");
+        assert_eq!(t.take_responses(), b"\x1b[>1;10;0c\x1b[>1;10;0c");
+        t.set_da2_response(b"\x1b[>41;300;0c");
+        assert_eq!(
+            t.apply_events(&[TerminalEvent::SecondaryDeviceAttributes]),
+            b"\x1b[>41;300;0c"
+        );
+    }
+
+    #[test]
+    fn xtsave_and_xtrestore_private_modes() {
+        let mut t = terminal(4, 5, b"\x1b[?25s\x1b[?25l");
+        assert!(!t.cursor_visible());
+        t.feed(b"\x1b[?25r");
+        assert!(t.cursor_visible());
+
+        t.feed(b"\x1b[?1049;7s\x1b[?1049h\x1b[?7l");
+        assert!(t.alt_screen);
+        t.feed(b"\x1b[?1049;7r");
+        assert!(!t.alt_screen);
+        assert!(t.autowrap());
+        // CSI r without `?` is still DECSTBM
+        t.feed(b"\x1b[2;3r");
+        assert_eq!(t.top_margin, 1);
+    }
+
+    #[test]
+    fn save_and_restore_modes_directly() {
+        let mut t = Terminal::new(4, 2);
+        t.save_modes(&[7, 2004]);
+        t.feed(b"\x1b[?7l\x1b[?2004h");
+        t.restore_modes(&[7, 2004, 1000]);
+        assert!(t.autowrap());
+        assert!(!t.mode_enabled(true, 2004));
+    }
+
+    #[test]
+    fn ansi_with_cursor_inverts_the_cursor_cell() {
+        let mut t = terminal(4, 1, b"a\x1b[7mb\x1b[0mc\x1b[1;3H");
+        assert_eq!(t.render_to_ansi_with_cursor(), "a\x1b[7mbc\x1b[0m \n");
+        // An already inverse cell flips back to normal under the cursor
+        t.feed(b"\x1b[1;2H");
+        assert_eq!(t.render_to_ansi_with_cursor(), "abc \n");
+        t.feed(b"\x1b[?25l");
+        assert_eq!(t.render_to_ansi_with_cursor(), t.render_to_ansi());
+    }
+
+    #[test]
+    fn render_options_cover_every_combination() {
+        let t = terminal(3, 2, b"a");
+        let render = |trim_trailing, crlf, final_newline| {
+            t.render_with_options(RenderOptions {
+                trim_trailing,
+                line_ending: if crlf {
+                    LineEnding::CrLf
+                } else {
+                    LineEnding::Lf
+                },
+                final_newline,
+            })
+        };
+        assert_eq!(render(false, false, true), t.render_content());
+        assert_eq!(render(false, false, true), "a  \n   \n");
+        assert_eq!(render(true, false, true), "a\n\n");
+        assert_eq!(render(false, true, true), "a  \r\n   \r\n");
+        assert_eq!(render(true, true, true), "a\r\n\r\n");
+        assert_eq!(render(false, false, false), "a  \n   ");
+        assert_eq!(render(true, false, false), "a\n");
+        assert_eq!(render(false, true, false), "a  \r\n   ");
+        assert_eq!(render(true, true, false), "a\r\n");
+    }
+
+    #[test]
+    fn ed_2_clears_outside_the_margins() {
+        let t = terminal(4, 5, b"aaaabbbbccccdddd\x1b[2;3r\x1b[?69h\x1b[2;3s\x1b[2J");
+        assert_eq!(t.render_content(), "    \n    \n    \n    \n    \n");
+    }
+
+    #[test]
+    fn ed_0_runs_to_the_screen_end_past_the_region() {
+        let t = terminal(4, 5, b"aaaabbbbccccdddd\x1b[2;3r\x1b[3;2H\x1b[J");
+        assert_eq!(t.render_content(), "aaaa\nbbbb\nc   \n    \n    \n");
+    }
+
+    #[test]
+    fn mode_2026_tracks_synchronized_output() {
+        let mut t = Terminal::new(4, 2);
+        assert!(!t.is_synchronized());
+        t.feed(b"\x1b[?2026h");
+        assert!(t.is_synchronized());
+        t.feed(b"\x1b[?2026l");
+        assert!(!t.is_synchronized());
+    }
+
+    #[test]
+    fn osc_4_updates_the_exported_palette() {
+        let mut t = Terminal::new(4, 2);
+        let before = t.palette_rgb();
+        t.feed(b"\x1b]4;5;rgb:12/34/56\x07");
+        let palette = t.palette_rgb();
+        assert_eq!(palette[5], (0x12, 0x34, 0x56));
+        assert_eq!(palette[4], before[4]);
+
+        t.feed(b"\x1b]4;1;#ff8000;300;#000000;2;rgb:f/ffff/0\x1b\\");
+        let palette = t.palette_rgb();
+        assert_eq!(palette[1], (255, 128, 0));
+        assert_eq!(palette[2], (255, 255, 0));
+        t.feed(b"\x1b]4;3;bogus\x07");
+        assert_eq!(t.palette_rgb()[3], before[3]);
+    }
+
+    #[test]
+    fn leaving_1047_clears_the_alternate_buffer() {
+        let mut t = terminal(4, 2, b"main\x1b[?1047h\x1b[Halt\x1b[?1047l");
+        assert_eq!(t.render_content(), "main\n    \n");
+        assert!(
+            t.inactive_grid
+                .iter()
+                .flatten()
+                .all(|cell| cell.character == ' ')
+        );
+        t.feed(b"\x1b[?1047h");
+        assert_eq!(t.render_content(), "    \n    \n");
+    }
+
+    #[test]
+    fn leaving_47_keeps_the_alternate_buffer() {
+        let t = terminal(4, 2, b"\x1b[?47h\x1b[Halt\x1b[?47l\x1b[?47h");
+        assert_eq!(t.render_content(), "alt \n    \n");
+    }
+
+    #[test]
+    fn feed_collecting_responses_returns_this_input_only() {
+        let mut t = terminal(10, 5, b"\x1b[5n");
+        assert_eq!(
+            t.feed_collecting_responses(b"\x1b[3;4Hab\x1b[6n"),
+            b"\x1b[3;6R"
+        );
+        assert_eq!(t.take_responses(), b"\x1b[0n");
+    }
+
+    #[test]
+    fn vertical_tab_and_form_feed_act_as_linefeed() {
+        let mut t = terminal(4, 3, b"\x0b");
+        assert_eq!(position(&t).0, 1);
+        t.feed(b"\x0c");
+        assert_eq!(position(&t).0, 2);
+        t.feed(b"x\x0c");
+        assert_eq!(position(&t).0, 2);
+        assert_eq!(t.render_content(), "    \nx   \n    \n");
+    }
+
+    #[test]
+    fn json_diff_sends_only_changed_rows() {
+        let a = terminal(4, 2, b"ab");
+        let b = terminal(4, 2, b"ab\x1b[2;1H\x1b[1;31mx\"\x1b[0m");
+        assert_eq!(
+            b.json_diff(&a),
+            r##"[{"row":1,"segments":[{"text":"x\"","fg":"#cd0000","bg":"#000000","bold":true},{"text":"  ","fg":"#e5e5e5","bg":"#000000"}]}]"##
+        );
+        assert_eq!(a.json_diff(&a), "[]");
+    }
+
+    #[test]
+    fn json_diff_marks_a_resize_and_sends_every_row() {
+        let a = terminal(4, 2, b"ab");
+        let c = Terminal::new(2, 1);
+        assert_eq!(
+            c.json_diff(&a),
+            r##"[{"resize":{"cols":2,"rows":1}},{"row":0,"segments":[{"text":"  ","fg":"#e5e5e5","bg":"#000000"}]}]"##
+        );
+    }
+
+    #[test]
+    fn enq_sends_the_configured_answerback() {
+        let mut t = terminal(4, 1, b"\x05");
+        assert!(t.take_responses().is_empty());
+        t.set_answerback("lettuce");
+        t.feed(b"a\x05b");
+        assert_eq!(t.take_responses(), b"lettuce");
+        assert_eq!(t.render_content(), "ab  \n");
+    }
+
+    #[test]
+    fn content_eq_ignores_padding_and_cursor() {
+        let a = terminal(6, 3, b"hi\r\nyo");
+        let mut b = terminal(3, 2, b"hi\r\nyo\x1b[1;3H");
+        assert!(a.content_eq(&b));
+        assert!(b.content_eq(&a));
+        // A styled blank is content, not padding
+        b.feed(b"\x1b[1m\x1b[1;3H ");
+        assert!(!a.content_eq(&b));
+        assert!(!a.content_eq(&terminal(6, 3, b"hi\r\nyo\r\nz")));
+    }
+
+    #[test]
+    fn tertiary_device_attributes_report_the_unit_id() {
+        let mut t = terminal(4, 1, b"\x1b[=c");
+        assert_eq!(t.take_responses(), b"\x1bP!|00000000\x1b\\");
+        t.set_unit_id("7E565445");
+        assert_eq!(
+            t.apply_events(&[TerminalEvent::TertiaryDeviceAttributes]),
+            b"\x1bP!|7E565445\x1b\\"
+        );
+    }
+
+    #[test]
+    fn single_row_ansi_starts_from_a_reset() {
+        let t = terminal(5, 2, b"\x1b[2;1Ha\x1b[1;32mbc\x1b[22md\x1b[0m");
+        assert_eq!(
+            t.render_row_to_ansi(1),
+            "\x1b[0ma\x1b[1;32mbc\x1b[22md\x1b[0m "
+        );
+        assert_eq!(t.render_row_to_ansi(0), "\x1b[0m     ");
+        assert_eq!(t.render_row_to_ansi(2), "");
+    }
+
+    #[test]
+    fn shrinking_clamps_the_cursor_before_printing() {
+        let mut t = terminal(10, 6, b"\x1b[6;9H");
+        t.resize(12, 3);
+        t.feed(b"x");
+        assert_eq!(t.grid[2][8].character, 'x');
+        t.resize(4, 3);
+        t.feed(b"\x1b[3;4H\x08y");
+        assert_eq!(t.grid[2][2].character, 'y');
+    }
+
+    #[test]
+    fn shrinking_clamps_saved_cursors() {
+        let mut t = terminal(10, 6, b"\x1b[6;9H\x1b7\x1b[s\x1b[?1049h");
+        t.resize(4, 3);
+        t.feed(b"\x1b[?1049l\x1b8y");
+        assert_eq!(position(&t), (2, 3));
+        assert_eq!(t.grid[2][3].character, 'y');
+    }
+
+    #[test]
+    fn zero_sized_terminals_are_raised_to_one_cell() {
+        let mut t = Terminal::new(0, 0);
+        assert_eq!(t.size(), (1, 1));
+        t.feed("ab中\x1b[5;5H\x1b[2J\tx\x1b[3@\x1b[2P\x1b[6n\r\n\x1b[1b".as_bytes());
+        let _ = t.render_to_ansi();
+        t.resize(0, 0);
+        assert_eq!(t.size(), (1, 1));
+        t.set_max_size(0, 0);
+        t.resize(5, 5);
+        assert_eq!(t.size(), (1, 1));
+        t.feed(b"zz");
+        assert_eq!(t.render_content(), "z\n");
+    }
+
+    #[test]
+    fn osc_104_resets_listed_palette_entries() {
+        let mut t = Terminal::new(4, 1);
+        let defaults = t.palette_rgb();
+        t.feed(b"\x1b]4;1;#010101;2;#020202;3;#030303\x07\x1b]104;1\x07");
+        let palette = t.palette_rgb();
+        assert_eq!(palette[1], defaults[1]);
+        assert_eq!(palette[2], (2, 2, 2));
+        assert_eq!(palette[3], (3, 3, 3));
+
+        // Unparsable indices are skipped, not treated as a full reset
+        t.feed(b"\x1b]104;2;x\x07");
+        assert_eq!(t.palette_rgb()[2], defaults[2]);
+        assert_eq!(t.palette_rgb()[3], (3, 3, 3));
+        t.feed(b"\x1b]104\x07");
+        assert_eq!(t.palette_rgb(), defaults);
+    }
+
+    #[test]
+    fn osc_104_with_an_empty_list_resets_everything() {
+        let mut t = terminal(4, 1, b"\x1b]4;1;#010101\x07\x1b]104;zz\x07");
+        assert_eq!(t.palette_rgb()[1], (1, 1, 1));
+        t.feed(b"\x1b]104;\x07");
+        assert_eq!(t.palette_rgb()[1], default_palette()[1]);
+    }
+
+    #[test]
+    fn print_wrapped_keeps_wide_glyphs_whole() {
+        let mut t = terminal(5, 3, b"\x1b[?7l");
+        t.print_wrapped("abcd中ef\nx", Attributes::default().with_bold(true));
+        assert_eq!(t.render_content(), "abcd \n中ef \nx    \n");
+        assert!(t.grid[1][1].continuation);
+        assert!(t.grid[0][0].attrs.bold);
+        assert!(!t.autowrap());
+    }
+
+    #[test]
+    fn decst8c_restores_stops_every_eight_columns() {
+        let mut t = terminal(20, 1, b"\x1b[3g\t");
+        assert_eq!(position(&t).1, 19);
+        t.feed(b"\x1b[?5W\r\t");
+        assert_eq!(position(&t).1, 8);
+        t.feed(b"\t");
+        assert_eq!(position(&t).1, 16);
+        // Other `?W` forms are ignored
+        t.feed(b"\x1b[3g\x1b[?4W\r\t");
+        assert_eq!(position(&t).1, 19);
+        t.reset_tab_stops_default();
+        t.feed(b"\r\t");
+        assert_eq!(position(&t).1, 8);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn line_hook_sees_lines_leaving_the_primary_screen() {
+        let (sender, lines) = std::sync::mpsc::channel();
+        let mut t = Terminal::new(5, 2);
+        t.set_on_line_complete(move |line| sender.send(line.to_string()).unwrap());
+        t.feed(b"one\r\ntwo\r\nthr\r\nfour");
+        assert_eq!(lines.try_iter().collect::<Vec<_>>(), ["one", "two"]);
+
+        // Scrolling the alternate screen reports nothing
+        t.feed(b"\x1b[?1049h\r\n\r\n\r\n\x1b[?1049l\r\n");
+        assert_eq!(lines.try_iter().collect::<Vec<_>>(), ["thr"]);
+        assert_eq!(t.scrollback.len(), 3);
+    }
+
+    #[test]
+    fn terminal_with_hooks_can_move_across_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Terminal>();
+    }
+
+    #[test]
+    fn overwriting_either_half_of_a_wide_char_clears_the_pair() {
+        let mut t = terminal(10, 2, "中x".as_bytes());
+        t.feed(b"\x1b[1;2Ha");
+        assert_eq!(t.cell(0, 0).unwrap().character(), ' ');
+        assert_eq!(t.cell(0, 1).unwrap().character(), 'a');
+        assert!(!t.cell(0, 1).unwrap().is_continuation());
+        assert_eq!(t.cell(0, 2).unwrap().character(), 'x');
+
+        t.feed("\x1b[2H中\x1b[2Hb".as_bytes());
+        assert_eq!(t.cell(1, 1).unwrap().character(), ' ');
+        assert!(!t.cell(1, 1).unwrap().is_continuation());
+    }
+
+    #[test]
+    fn wide_char_over_a_pair_boundary_clears_both_neighbours() {
+        let t = terminal(10, 2, "\x1b[1;5H中中\x1b[1;6H文".as_bytes());
+        assert_eq!(t.cell(0, 4).unwrap().character(), ' ');
+        assert_eq!(t.cell(0, 5).unwrap().character(), '文');
+        assert!(t.cell(0, 6).unwrap().is_continuation());
+        assert_eq!(t.cell(0, 7).unwrap().character(), ' ');
+        assert!(!t.cell(0, 7).unwrap().is_continuation());
+    }
+
+    #[test]
+    fn total_lines_counts_scrollback_and_screen() {
+        let mut t = Terminal::new(5, 3);
+        assert_eq!(t.total_lines(), 3);
+        t.feed(b"a\r\nb\r\nc\r\nd\r\ne");
+        assert_eq!(t.scrollback.len(), 2);
+        assert_eq!(t.total_lines(), 5);
+    }
+
+    #[test]
+    fn pen_carries_into_a_blank_alternate_screen() {
+        let mut t = terminal(5, 2, b"xy\x1b[1;31m\x1b[?1049h");
+        for col in 0..5 {
+            assert_eq!(*t.cell(0, col).unwrap(), Cell::default());
+        }
+        t.feed(b"z");
+        let attrs = t.attrs_at(0, 2).unwrap();
+        assert!(attrs.bold);
+        assert_eq!(attrs.foreground, Color::Red);
+    }
+
+    #[test]
+    fn colon_sgr_colors_survive_truncated_sequences() {
+        let t = terminal(
+            10,
+            2,
+            b"\x1b[1;38:2:1:2:3mx\x1b[38;5mz\x1b[38;2;255my\x1b[48;2mw\x1b[38:5mv",
+        );
+        for col in 0..5 {
+            let attrs = t.attrs_at(0, col).unwrap();
+            assert!(attrs.bold);
+            assert_eq!(attrs.foreground, Color::Rgb(1, 2, 3));
+            assert_eq!(attrs.background, Color::Default);
+        }
+
+        let restored = Terminal::from_rle(&t.to_rle()).unwrap();
+        assert_eq!(restored.attrs_at(0, 1), t.attrs_at(0, 1));
+        assert!(restored.attrs_at(0, 9).unwrap().is_default());
+    }
+
+    #[test]
+    fn host_title_is_overwritten_by_osc() {
+        let mut t = Terminal::new(10, 2);
+        assert_eq!(t.title(), "");
+        t.set_title("host");
+        assert_eq!(t.title(), "host");
+        t.feed(b"\x1b]2;vim; main.rs\x07");
+        assert_eq!(t.title(), "vim; main.rs");
+        t.feed(b"\x1b]0;x\x1b\\");
+        assert_eq!(t.title(), "x");
+        t.feed(b"\x1b]2;\x07");
+        assert_eq!(t.title(), "");
+        assert_eq!(t.render_content().trim(), "");
+    }
+
+    #[test]
+    fn carriage_return_cancels_a_pending_wrap() {
+        let mut t = terminal(5, 3, b"abcde");
+        assert_eq!(position(&t), (0, 4));
+        assert!(t.wrap_pending);
+        t.feed(b"\rX");
+        assert_eq!(line(&t, 0), "Xbcde");
+        assert_eq!(line(&t, 1), "");
+        assert_eq!(position(&t), (0, 1));
+
+        let t = terminal(5, 2, b"abcde\r\nxy");
+        assert_eq!((line(&t, 0), line(&t, 1)), ("abcde".into(), "xy".into()));
+        assert!(t.scrollback.is_empty());
+    }
+
+    #[test]
+    fn pixel_to_cell_clamps_to_the_grid() {
+        let t = Terminal::new(80, 24);
+        assert_eq!(t.pixel_to_cell(0, 0, 10, 20), (1, 1));
+        assert_eq!(t.pixel_to_cell(9, 19, 10, 20), (1, 1));
+        assert_eq!(t.pixel_to_cell(10, 20, 10, 20), (2, 2));
+        assert_eq!(t.pixel_to_cell(795, 475, 10, 20), (80, 24));
+        assert_eq!(t.pixel_to_cell(5000, 5000, 10, 20), (80, 24));
+        assert_eq!(t.pixel_to_cell(3, 4, 0, 0), (4, 5));
+    }
+
+    #[test]
+    fn truecolor_ansi_resolves_colors_through_the_palette() {
+        let t = terminal(3, 1, b"\x1b[31mr\x1b[0mx\x1b[38;5;196mi");
+        let ansi = t.render_to_truecolor_ansi();
+        let (r, g, b) = t.palette_rgb()[1];
+        assert!(
+            ansi.starts_with(&format!("\x1b[38;2;{r};{g};{b};48;2;0;0;0mr")),
+            "{ansi:?}"
+        );
+        assert!(!ansi.contains("\x1b[31m"));
+        assert!(ansi.contains("38;2;255;0;0"));
+    }
+
+    #[test]
+    fn blank_until_something_is_drawn() {
+        let mut t = Terminal::new(4, 2);
+        assert!(t.is_blank());
+        t.feed(b"x");
+        assert!(!t.is_blank());
+        t.feed(b"\x1b[2J");
+        assert!(t.is_blank());
+        t.feed(b"\x1b[44m \x1b[0m");
+        assert!(!t.is_blank());
+    }
+
+    #[test]
+    fn keypad_mode_toggles_with_deckpam_and_deckpnm() {
+        let mut t = Terminal::new(4, 2);
+        assert!(!t.application_keypad());
+        t.feed(b"\x1b=");
+        assert!(t.application_keypad());
+        t.feed(b"\x1b>");
+        assert!(!t.application_keypad());
+        t.feed(b"\x1b=\x1b[!p");
+        assert!(!t.application_keypad());
+        assert!(t.is_blank());
+    }
+
+    #[test]
+    fn arrow_encoding_follows_cursor_key_mode() {
+        let mut t = Terminal::new(4, 2);
+        assert_eq!(t.encode_arrow(Direction::Up), b"\x1b[A");
+        t.feed(b"\x1b[?1h");
+        assert!(t.application_cursor_keys());
+        assert_eq!(t.encode_arrow(Direction::Up), b"\x1bOA");
+        assert_eq!(t.encode_arrow(Direction::Left), b"\x1bOD");
+        t.feed(b"\x1b[?1l");
+        assert_eq!(t.encode_arrow(Direction::Right), b"\x1b[C");
+        t.feed(b"\x1b[?1h\x1b[!p");
+        assert!(!t.application_cursor_keys());
+    }
+
+    #[test]
+    fn hyperlinks_coalesce_adjacent_cells() {
+        let mut t = terminal(
+            20,
+            3,
+            b"see \x1b]8;id=a;http://x.test/a;b\x1b\\docs\x1b]8;;\x1b\\ now",
+        );
+        let docs = (vec![(0, 4..8)], String::from("http://x.test/a;b"));
+        assert_eq!(t.hyperlinks(), vec![docs.clone()]);
+
+        t.feed(
+            "\r\n\x1b]8;;http://y\x07中z\x1b]8;;\x07 \x1b]8;;http://y\x07q\x1b]8;;\x07".as_bytes(),
+        );
+        let y = (vec![(1, 0..3), (1, 4..5)], String::from("http://y"));
+        assert_eq!(t.hyperlinks(), vec![docs, y]);
+        assert_eq!(t.links.len(), 2);
+
+        t.feed(b"\x1b[2J");
+        assert!(t.hyperlinks().is_empty());
+    }
+
+    #[test]
+    fn wrapped_hyperlinks_are_listed_once() {
+        let t = terminal(5, 3, b"ab\x1b]8;;http://w\x07cdefg\x1b]8;;\x07h");
+        assert_eq!(
+            t.hyperlinks(),
+            vec![(vec![(0, 2..5), (1, 0..2)], String::from("http://w"))]
+        );
+    }
+
+    #[test]
+    fn unreferenced_links_are_collected() {
+        let mut t = Terminal::new(10, 2);
+        for n in 0..2000 {
+            t.feed(format!("\r\x1b]8;;http://x.test/{n}\x07ab\x1b]8;;\x07").as_bytes());
+        }
+        assert!(t.links.len() <= LINK_GC_THRESHOLD);
+        assert_eq!(t.links.len(), t.link_index.len());
+        assert_eq!(
+            t.hyperlinks(),
+            vec![(vec![(0, 0..2)], String::from("http://x.test/1999"))]
+        );
+
+        // Links still on screen survive a sweep and keep resolving
+        let mut t = terminal(10, 2, b"\x1b]8;;http://kept\x07k\x1b]8;;\x07");
+        for n in 0..LINK_GC_THRESHOLD * 2 {
+            t.feed(format!("\x1b]8;;http://x.test/{n}\x07\x1b]8;;\x07").as_bytes());
+        }
+        let kept = |cols| vec![(vec![(0, cols)], String::from("http://kept"))];
+        assert_eq!(t.hyperlinks(), kept(0..1));
+        t.feed(b"\x1b]8;;http://kept\x07m");
+        assert_eq!(t.hyperlinks(), kept(0..2));
+    }
+
+    #[test]
+    fn modify_keys_does_not_touch_the_pen() {
+        let mut t = terminal(10, 1, b"\x1b[1;31m\x1b[>4;2m\x1b[?4mx");
+        let attrs = t.attrs_at(0, 0).unwrap();
+        assert!(attrs.bold);
+        assert_eq!(attrs.foreground, Color::Red);
+        assert_eq!(t.modify_keys(4), Some(2));
+
+        t.feed(b"\x1b[>4m");
+        assert_eq!(t.modify_keys(4), None);
+        t.feed(b"\x1b[>0;1m");
+        assert_eq!(t.modify_keys(0), Some(1));
+        t.feed(b"\x1b[>m");
+        assert_eq!(t.modify_keys(0), None);
+    }
+
+    #[test]
+    fn row_rgb_resolves_colors_and_flags() {
+        let t = terminal(4, 2, "\x1b[1;31;44ma\x1b[0m中".as_bytes());
+        let row = t.row_rgb(0);
+        let palette = t.palette_rgb();
+        assert_eq!(row.len(), 4);
+        assert_eq!(row[0], (String::from("a"), palette[1], palette[4], 1));
+        assert_eq!(
+            row[1],
+            (
+                String::from("中"),
+                DEFAULT_FOREGROUND,
+                DEFAULT_BACKGROUND,
+                0
+            )
+        );
+        assert_eq!(row[2].0, "");
+        assert_eq!(row[2].3, 1 << 4);
+        assert!(t.row_rgb(5).is_empty());
+    }
+
+    #[test]
+    fn line_feed_below_the_bottom_margin_does_not_scroll() {
+        let mut t = terminal(4, 5, b"top\x1b[1;3r\x1b[4;1Hx\n");
+        assert_eq!(t.cursor().row, 4);
+        assert_eq!(line(&t, 0), "top");
+        assert_eq!(line(&t, 3), "x");
+
+        // Already on the last row and outside the region: stays put
+        t.feed(b"\n");
+        assert_eq!(t.cursor().row, 4);
+        assert_eq!(line(&t, 0), "top");
+        assert_eq!(line(&t, 3), "x");
+
+        // On the bottom margin the region scrolls
+        t.feed(b"\x1b[3;1Hy\n");
+        assert_eq!(line(&t, 0), "");
+        assert_eq!(line(&t, 1), "y");
+        assert_eq!(line(&t, 3), "x");
+    }
+
+    #[test]
+    fn render_one_shot() {
+        assert_eq!(
+            render(6, 2, b"\x1b[1;31mhi\x1b[0m\r\nthere"),
+            "hi    \nthere \n"
+        );
+    }
+
+    #[test]
+    fn xtgettcap_reports_known_capabilities() {
+        let mut t = Terminal::new(4, 1);
+        t.feed(b"\x1bP+q636f6c6f7273\x1b\\");
+        assert_eq!(t.take_responses(), b"\x1bP1+r636f6c6f7273=323536\x1b\\");
+
+        t.feed(b"\x1bP+q436F;544E\x1b\\");
+        assert_eq!(
+            String::from_utf8(t.take_responses()).unwrap(),
+            "\x1bP1+r436F=323536\x1b\\\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\"
+        );
+
+        for query in [
+            &b"\x1bP+q7878;436F\x1b\\"[..],
+            b"\x1bP+qzz\x1b\\",
+            b"\x1bP+q\x1b\\",
+        ] {
+            t.feed(query);
+            assert_eq!(t.take_responses(), b"\x1bP0+r\x1b\\");
+        }
+        assert!(t.is_blank());
+    }
+}
@@ -0,0 +1,45 @@
+// Drives the terminal the way a downstream crate would: events are built by
+// hand from public types and applied without going through the parser
+use lettuce::parser::{Attributes, Color, TerminalEvent};
+use lettuce::terminal::Terminal;
+
+#[test]
+fn hand_built_events_apply_through_the_public_api() {
+    let mut terminal = Terminal::new(6, 2);
+    let red = Attributes {
+        foreground: Color::Red,
+        bold: true,
+        ..Attributes::default()
+    };
+    terminal.process_events(&[
+        TerminalEvent::Print {
+            char: 'h',
+            attrs: red.clone(),
+        },
+        TerminalEvent::PrintStr {
+            text: "ey".into(),
+            attrs: Attributes::default().with_italic(true),
+        },
+        TerminalEvent::CarriageReturn,
+        TerminalEvent::Linefeed,
+        TerminalEvent::Print {
+            char: '!',
+            attrs: Attributes::default(),
+        },
+    ]);
+
+    assert_eq!(terminal.render_content(), "hey   \n!     \n");
+    let cell = terminal.cell(0, 0).unwrap();
+    assert_eq!(cell.character(), 'h');
+    assert_eq!(cell.attrs(), &red);
+    assert!(terminal.cell(0, 1).unwrap().attrs().italic);
+    let cursor = terminal.cursor();
+    assert_eq!((cursor.row, cursor.col), (1, 1));
+}
+
+#[test]
+fn hand_built_queries_produce_replies() {
+    let mut terminal = Terminal::new(6, 2);
+    let replies = terminal.apply_events(&[TerminalEvent::DeviceStatusReport(6)]);
+    assert_eq!(replies, b"\x1b[1;1R");
+}